@@ -0,0 +1,46 @@
+//! ビルドしたソースの git リビジョンを `PLAYGROUND_GIT_REV` として埋め込む
+//!
+//! 実験プログラムを実行する場所にソースや git がなくても、結果に添える環境の情報にリビジョンを残せるようにします。
+//! git がない場合や git のリポジトリの外でビルドした場合は埋め込みません。
+
+use std::{path::Path, process::Command};
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    // ソースを変更した場合（`-dirty` の有無が変わる）と、コミットやステージングをした場合に埋め込み直す
+    for path in ["build.rs", "Cargo.toml", "src", "examples", "tests"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    let git_dir = match git(&["rev-parse", "--absolute-git-dir"]) {
+        Some(git_dir) => git_dir,
+        None => return,
+    };
+    let git_dir = Path::new(&git_dir);
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("index").display());
+    if let Some(head) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        println!("cargo:rerun-if-changed={}", git_dir.join(head).display());
+    }
+    println!(
+        "cargo:rerun-if-changed={}",
+        git_dir.join("packed-refs").display()
+    );
+
+    let rev = match git(&["rev-parse", "--short", "HEAD"]) {
+        Some(rev) => rev,
+        None => return,
+    };
+    // このクレートのディレクトリ以下にコミットしていない変更があれば `-dirty` を付ける
+    let rev = match git(&["status", "--porcelain", "--untracked-files=no", "--", "."]) {
+        Some(status) if !status.is_empty() => format!("{}-dirty", rev),
+        _ => rev,
+    };
+    println!("cargo:rustc-env=PLAYGROUND_GIT_REV={}", rev);
+}
@@ -1,21 +1,6 @@
-use nix::unistd::{execve, fork, getpid, ForkResult};
-use std::ffi::CString;
+use clap::Parser;
+use playground::cli::fork_and_exec::{run, Args};
 
 fn main() {
-    match unsafe { fork() } {
-        Ok(ForkResult::Parent { child, .. }) => {
-            println!(
-                "I'm parent! my pid is {} and the pid of my child is {}.",
-                getpid(),
-                child
-            );
-        }
-        Ok(ForkResult::Child) => {
-            println!("I'm child! my pid is {}.", getpid());
-            let path = CString::new("/bin/echo").unwrap();
-            let args = [&path, &CString::new("hello").unwrap()];
-            execve(&path, &args, &[CString::new("").unwrap()]).expect("execve() failed.");
-        }
-        Err(_) => eprintln!("fork() failed."),
-    }
+    run(Args::parse());
 }
@@ -1,3 +1,6 @@
+use clap::Parser;
+use playground::cli::busy_loop::{run, Args};
+
 fn main() {
-  loop {}
+    run(Args::parse());
 }
@@ -1,14 +1,6 @@
-use nix::unistd::getpid;
-use std::process::Command;
+use clap::Parser;
+use playground::cli::mmap::{run, Args};
 
 fn main() {
-    let pid = getpid();
-    println!("*** memory map before memory allocation ***");
-    let mut cmd = Command::new("cat");
-    cmd.arg(format!("/proc/{}/maps", pid));
-    let output = cmd.output().expect("failed to execute command").stdout;
-    let o = std::str::from_utf8(&output).unwrap();
-    println!("{}", o);
-
-    std::process::exit(nix::libc::EXIT_SUCCESS);
+    run(Args::parse());
 }
@@ -1,6 +1,6 @@
+use clap::Parser;
+use playground::cli::ppidloop::{run, Args};
+
 fn main() {
-    println!("Get PPID. Ctrl + C to stop. ");
-    loop {
-        nix::unistd::getppid();
-    }
+    run(Args::parse());
 }
@@ -0,0 +1,185 @@
+//! CPU の割り当て（アフィニティ）の取得と設定
+
+use clap::ArgEnum;
+use nix::{
+    errno::Errno,
+    libc,
+    sched::{sched_getaffinity, sched_setaffinity, CpuSet},
+    unistd::Pid,
+};
+use std::{fmt, str::FromStr};
+
+/// 子プロセスへの CPU の割り当て方
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// すべての子プロセスが指定した CPU すべてで動作できる
+    Shared,
+    /// 子プロセスを指定した CPU に 1 つずつ順番に固定する
+    RoundRobin,
+}
+
+impl Placement {
+    /// `id` 番目の子プロセスが動作する CPU を返します。
+    pub fn cpus_for(&self, id: usize, cpus: &[usize]) -> Vec<usize> {
+        match self {
+            Placement::Shared => cpus.to_vec(),
+            Placement::RoundRobin => vec![cpus[id % cpus.len()]],
+        }
+    }
+
+    /// メタデータに記録する名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Placement::Shared => "shared",
+            Placement::RoundRobin => "round-robin",
+        }
+    }
+}
+
+/// `--cpus 0,2-3` のように指定する CPU の一覧
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuList(pub Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_cpu_list(s).map(CpuList)
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_cpu_list(&self.0))
+    }
+}
+
+/// `requested` が指定されていれば呼び出したプロセスをその CPU に固定し、
+/// 動作可能な CPU の番号を返します。
+///
+/// 子プロセスを作る前に呼び出すことで、ループ回数の推定も同じ CPU で行われます。
+pub fn restrict(requested: Option<&CpuList>) -> nix::Result<Vec<usize>> {
+    if let Some(CpuList(cpus)) = requested {
+        set_cpus(cpus)?;
+    }
+    current_cpus()
+}
+
+/// 呼び出したプロセスが動作可能な CPU の番号を昇順で返します。
+pub fn current_cpus() -> nix::Result<Vec<usize>> {
+    let set = sched_getaffinity(Pid::from_raw(0))?;
+    let mut cpus = Vec::new();
+    for cpu in 0..CpuSet::count() {
+        if set.is_set(cpu)? {
+            cpus.push(cpu);
+        }
+    }
+    Ok(cpus)
+}
+
+/// 呼び出したプロセスを `cpus` の CPU でのみ動作させます。
+pub fn set_cpus(cpus: &[usize]) -> nix::Result<()> {
+    let mut set = CpuSet::new();
+    for &cpu in cpus {
+        set.set(cpu)?;
+    }
+    sched_setaffinity(Pid::from_raw(0), &set)
+}
+
+/// 呼び出したスレッドが現在動作している CPU の番号を返します。
+pub fn current_cpu() -> nix::Result<usize> {
+    let cpu = unsafe { libc::sched_getcpu() };
+    Errno::result(cpu).map(|cpu| cpu as usize)
+}
+
+/// `taskset -c` と同じ `0,2-3` 形式の CPU の一覧を解釈します。
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .map_err(|e| format!("invalid CPU number {:?}: {}", s, e))
+    };
+    let mut cpus = Vec::new();
+    for range in list.split(',') {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("invalid CPU range {:?}", range));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(parse(range)?),
+        }
+    }
+    if let Some(&cpu) = cpus.iter().find(|&&cpu| cpu >= CpuSet::count()) {
+        return Err(format!(
+            "CPU number {} should be < {}",
+            cpu,
+            CpuSet::count()
+        ));
+    }
+    if cpus.is_empty() {
+        return Err("no CPU specified".to_string());
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// CPU 番号の一覧を `taskset -c` と同じ `0,2-3` 形式の文字列にします。
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cpu_list_accepts_numbers_and_ranges() {
+        assert_eq!(parse_cpu_list("0"), Ok(vec![0]));
+        assert_eq!(parse_cpu_list("0,2-3"), Ok(vec![0, 2, 3]));
+        assert_eq!(parse_cpu_list(" 1 , 3-4 "), Ok(vec![1, 3, 4]));
+        assert_eq!(parse_cpu_list("2-2"), Ok(vec![2]));
+    }
+
+    #[test]
+    fn parse_cpu_list_sorts_and_dedups() {
+        assert_eq!(parse_cpu_list("3,0-2,1"), Ok(vec![0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn parse_cpu_list_rejects_invalid_lists() {
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("a").is_err());
+        assert!(parse_cpu_list("0,").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("1-").is_err());
+        assert!(parse_cpu_list(&CpuSet::count().to_string()).is_err());
+    }
+
+    #[test]
+    fn format_cpu_list_is_inverse_of_parse() {
+        for list in ["0", "0,2-3", "0-3,5,7-8"] {
+            assert_eq!(format_cpu_list(&parse_cpu_list(list).unwrap()), list);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! `O_DIRECT` で使う、アドレスを揃えたバッファと対象ごとのアラインメントの要件
+//!
+//! `O_DIRECT` では、バッファのアドレス、ファイル上の位置、I/O サイズのいずれも
+//! 対象が求める単位に揃っていなければ、カーネルが `EINVAL` を返します。
+//!
+//! - ブロックデバイス: `BLKSSZGET` で得た論理ブロックサイズ
+//! - 通常のファイル: `statx()` の `stx_dio_mem_align` と `stx_dio_offset_align`（Linux 6.1 以降）
+//!
+//! どちらも得られない場合は、メモリはページサイズ、位置は 512 バイトに揃えることにします。
+
+use nix::{
+    errno::Errno,
+    libc::{self, c_int},
+    unistd::{sysconf, SysconfVar},
+};
+use std::{
+    alloc::{self, Layout},
+    fs::File,
+    ffi::CStr,
+    mem,
+    ops::{Deref, DerefMut},
+    os::unix::{fs::FileTypeExt, io::AsRawFd},
+    ptr::{self, NonNull},
+    slice,
+};
+
+/// `statx()` でダイレクト I/O のアラインメントを問い合わせるフラグ（libc にまだ定義がない）
+const STATX_DIOALIGN: libc::c_uint = 0x2000;
+
+/// 要件が分からない場合の、ファイル上の位置の単位
+const DEFAULT_OFFSET_ALIGN: usize = 512;
+
+nix::ioctl_read_bad!(blksszget, libc::BLKSSZGET, c_int);
+
+/// 対象がダイレクト I/O に求めるアラインメント（バイト）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Alignment {
+    /// バッファのアドレスの単位
+    pub memory: usize,
+    /// ファイル上の位置と I/O サイズの単位
+    pub offset: usize,
+    /// 要件を得た方法（`BLKSSZGET`, `statx` または `default`）
+    pub source: &'static str,
+}
+
+impl Alignment {
+    /// 開いた対象のアラインメントの要件を調べます。
+    pub fn of(file: &File) -> Result<Alignment, String> {
+        let metadata = file
+            .metadata()
+            .map_err(|e| format!("fstat() failed: {}", e))?;
+        if metadata.file_type().is_block_device() {
+            let mut size: c_int = 0;
+            unsafe { blksszget(file.as_raw_fd(), &mut size) }
+                .map_err(|e| format!("ioctl(BLKSSZGET) failed: {}", e))?;
+            return Ok(Alignment {
+                memory: size as usize,
+                offset: size as usize,
+                source: "BLKSSZGET",
+            });
+        }
+        let dio_align = statx_dio_align(file).map_err(|e| format!("statx() failed: {}", e))?;
+        if let Some((memory, offset)) = dio_align {
+            return Ok(Alignment {
+                memory,
+                offset,
+                source: "statx",
+            });
+        }
+        Ok(Alignment {
+            memory: page_size(),
+            offset: DEFAULT_OFFSET_ALIGN,
+            source: "default",
+        })
+    }
+
+    /// I/O サイズとすべての位置が要件を満たしているか確かめます。
+    pub fn check(&self, block_size: usize, offsets: &[u64]) -> Result<(), String> {
+        if block_size % self.offset != 0 {
+            return Err(format!(
+                "block size ({} bytes) should be multiple of {} bytes for O_DIRECT ({})",
+                block_size, self.offset, self.source
+            ));
+        }
+        if let Some(offset) = offsets
+            .iter()
+            .find(|&&o| o % self.offset as u64 != 0)
+        {
+            return Err(format!(
+                "offset {} should be multiple of {} bytes for O_DIRECT ({})",
+                offset, self.offset, self.source
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `statx()` でダイレクト I/O のアラインメントを問い合わせます。
+///
+/// カーネルやファイルシステムが対応していない、またはダイレクト I/O ができない場合は `None` を返します。
+fn statx_dio_align(file: &File) -> nix::Result<Option<(usize, usize)>> {
+    let mut stx: libc::statx = unsafe { mem::zeroed() };
+    let empty = CStr::from_bytes_with_nul(b"\0").expect("should be nul-terminated");
+    let ret = unsafe {
+        libc::statx(
+            file.as_raw_fd(),
+            empty.as_ptr(),
+            libc::AT_EMPTY_PATH,
+            STATX_DIOALIGN,
+            &mut stx,
+        )
+    };
+    Errno::result(ret)?;
+    if stx.stx_mask & STATX_DIOALIGN == 0 {
+        return Ok(None);
+    }
+    // libc の statx 構造体には stx_dio_mem_align と stx_dio_offset_align がまだないので、
+    // カーネルの定義どおり stx_mnt_id の直後から読み出す
+    let [memory, offset] = unsafe {
+        ptr::addr_of!(stx.stx_mnt_id)
+            .cast::<u8>()
+            .add(mem::size_of::<u64>())
+            .cast::<[u32; 2]>()
+            .read_unaligned()
+    };
+    // 0 はダイレクト I/O に対応していないことを表す
+    Ok((memory != 0 && offset != 0).then_some((memory as usize, offset as usize)))
+}
+
+fn page_size() -> usize {
+    match sysconf(SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) => size as usize,
+        _ => 4096,
+    }
+}
+
+/// アドレスを揃えて確保した、0 で初期化したバッファ
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// 確保した領域を所有しているだけなので、別のスレッドに渡しても共有しても問題ない
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+impl AlignedBuf {
+    /// `align` の倍数のアドレスに `len` バイトを確保します。
+    ///
+    /// `len` は 0 より大きく、`align` は 2 のべき乗でなければいけません。
+    pub fn new(len: usize, align: usize) -> AlignedBuf {
+        assert!(len > 0, "empty aligned buffer");
+        let layout = Layout::from_size_align(len, align).expect("invalid buffer alignment");
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        match NonNull::new(ptr) {
+            Some(ptr) => AlignedBuf { ptr, layout },
+            None => alloc::handle_alloc_error(layout),
+        }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIGN_4K: Alignment = Alignment {
+        memory: 4096,
+        offset: 4096,
+        source: "statx",
+    };
+
+    #[test]
+    fn check_accepts_aligned_block_size_and_offsets() {
+        assert_eq!(ALIGN_4K.check(4096, &[0, 4096, 1 << 30]), Ok(()));
+        assert_eq!(ALIGN_4K.check(1 << 20, &[]), Ok(()));
+    }
+
+    #[test]
+    fn check_rejects_unaligned_block_size() {
+        let e = ALIGN_4K.check(512, &[0]).unwrap_err();
+        assert!(e.contains("block size (512 bytes)"), "{}", e);
+        assert!(ALIGN_4K.check(6144, &[0]).is_err());
+    }
+
+    #[test]
+    fn check_rejects_first_unaligned_offset() {
+        let e = ALIGN_4K.check(4096, &[0, 4096, 4608, 512]).unwrap_err();
+        assert!(e.contains("offset 4608 "), "{}", e);
+    }
+
+    #[test]
+    fn aligned_buf_is_aligned_and_zeroed() {
+        let buf = AlignedBuf::new(8192, 4096);
+        assert_eq!(buf.as_ptr() as usize % 4096, 0);
+        assert_eq!(buf.len(), 8192);
+        assert!(buf.iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,84 @@
+//! コマンドライン引数の検証と、各プログラムに共通の引数
+
+use clap::{Args, ErrorKind};
+use std::{fmt, num::ParseIntError};
+
+/// 引数の検証に失敗した理由
+#[derive(Debug)]
+pub enum ArgError {
+    /// 数値として解釈できない
+    NotANumber(ParseIntError),
+    /// 下限値より小さい
+    TooSmall { min: usize },
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::NotANumber(e) => write!(f, "should be number: {}", e),
+            ArgError::TooSmall { min } => write!(f, "should be >= {}", min),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArgError::NotANumber(e) => Some(e),
+            ArgError::TooSmall { .. } => None,
+        }
+    }
+}
+
+/// 与えられた引数を 1 以上の `usize` に変換します。
+///
+/// `clap` の `parse(try_from_str = positive)` としても使えます。
+pub fn positive(arg: &str) -> Result<usize, ArgError> {
+    match arg.parse::<usize>() {
+        Ok(r) if r >= 1 => Ok(r),
+        Ok(_) => Err(ArgError::TooSmall { min: 1 }),
+        Err(e) => Err(ArgError::NotANumber(e)),
+    }
+}
+
+/// 1 つずつは正しいが組み合わせが誤っている引数について `message` を表示し、
+/// `clap` が引数を解釈できなかった場合と同じく終了コード 2 で終了します。
+pub fn reject(message: impl fmt::Display) -> ! {
+    clap::Error::raw(ErrorKind::ValueValidation, format!("{}\n", message)).exit()
+}
+
+/// 測定を繰り返す回数
+///
+/// 各プログラムの引数に `#[clap(flatten)]` で加えると、`--repeat` と `--warmup` を受け付けます。
+#[derive(Args, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Repeat {
+    /// 測定を繰り返す回数。2 以上にすると平均値、標準偏差、中央値、最小値、最大値、95% 信頼区間を出力する
+    #[clap(long, parse(try_from_str = positive), default_value = "1")]
+    pub repeat: usize,
+    /// 記録する前に捨てる測定の回数
+    #[clap(long, default_value = "0")]
+    pub warmup: usize,
+}
+
+impl Repeat {
+    /// `measure` を `warmup` 回実行して結果を捨ててから、`repeat` 回実行した結果を返します。
+    pub fn run<T, F: FnMut() -> T>(&self, mut measure: F) -> Vec<T> {
+        for _ in 0..self.warmup {
+            measure();
+        }
+        (0..self.repeat).map(|_| measure()).collect()
+    }
+
+    /// [`Repeat::run`] と同じですが、`measure` がエラーを返したらそこで止めてエラーを返します。
+    pub fn try_run<T, E, F: FnMut() -> Result<T, E>>(&self, mut measure: F) -> Result<Vec<T>, E> {
+        for _ in 0..self.warmup {
+            measure()?;
+        }
+        (0..self.repeat).map(|_| measure()).collect()
+    }
+
+    /// 繰り返し測定したか（要約統計量を出力するか）
+    pub fn is_repeated(&self) -> bool {
+        self.repeat > 1
+    }
+}
@@ -0,0 +1,6 @@
+use clap::Parser;
+use playground::cli::plot::{run, Args};
+
+fn main() {
+    run(Args::parse());
+}
@@ -0,0 +1,292 @@
+//! CFS が各プロセスに与える CPU 時間の取り分の予測と、実測値との比較
+//!
+//! CFS は nice 値ごとに決まった重みの比で CPU 時間を配分します。
+//! 重みはカーネルの `sched_prio_to_weight` と同じ値を使います。
+
+use crate::{output::Record, policy::Policy, scheduler::ChildSetup};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// nice 値 -20 から 19 までの重み（`kernel/sched/core.c` の `sched_prio_to_weight`）
+const SCHED_PRIO_TO_WEIGHT: [u32; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291, //
+    /* -15 */ 29154, 23254, 18705, 14949, 11916, //
+    /* -10 */ 9548, 7620, 6100, 4904, 3906, //
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277, //
+    /*   0 */ 1024, 820, 655, 526, 423, //
+    /*   5 */ 335, 272, 215, 172, 137, //
+    /*  10 */ 110, 87, 70, 56, 45, //
+    /*  15 */ 36, 29, 23, 18, 15, //
+];
+
+/// `SCHED_IDLE` のプロセスの重み（`WEIGHT_IDLEPRIO`）
+const WEIGHT_IDLEPRIO: u32 = 3;
+
+/// nice 値に対応する重みを返します。
+pub fn weight(nice: i32) -> u32 {
+    SCHED_PRIO_TO_WEIGHT[(nice.clamp(-20, 19) + 20) as usize]
+}
+
+/// `ncpu` 個の CPU を重み `weights` で分け合ったときに、それぞれが使える CPU の数を返します。
+///
+/// 1 つのプロセスは同時に 1 つの CPU しか使えないため、
+/// 重みの比で配分すると 1 を超えるプロセスは 1 に抑え、残りを他のプロセスで分け直します。
+pub fn expected_cpus(weights: &[u32], ncpu: usize) -> Vec<f64> {
+    let mut alloc = vec![0.0; weights.len()];
+    let mut active: Vec<usize> = (0..weights.len()).collect();
+    let mut capacity = ncpu as f64;
+    while !active.is_empty() && capacity > 0.0 {
+        let total: f64 = active.iter().map(|&i| weights[i] as f64).sum();
+        let (capped, rest): (Vec<usize>, Vec<usize>) = active
+            .iter()
+            .partition(|&&i| capacity * weights[i] as f64 / total >= 1.0);
+        if capped.is_empty() {
+            for &i in &rest {
+                alloc[i] = capacity * weights[i] as f64 / total;
+            }
+            break;
+        }
+        for &i in &capped {
+            alloc[i] = 1.0;
+        }
+        capacity -= capped.len() as f64;
+        active = rest;
+    }
+    alloc
+}
+
+/// 子プロセス 1 つ分の CPU 時間の取り分
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Share {
+    /// プロセス番号
+    pub id: usize,
+    /// 子プロセスの nice 値
+    pub nice: i32,
+    /// CFS の重み
+    pub weight: u32,
+    /// 重みから予測した取り分（子プロセス全体の CPU 時間に対する割合）
+    pub expected: f64,
+    /// 実測した取り分
+    pub observed: f64,
+    /// 実測値と予測値の差（`observed - expected`）
+    pub deviation: f64,
+}
+
+/// 記録から子プロセスごとの CPU 時間の取り分を求め、重みから予測した値と比較します。
+///
+/// すべての子プロセスが CPU を取り合っている期間、
+/// つまり最初の子プロセスが負荷をかけ終えるまでに進んだ記録の数から取り分を求めます。
+/// 実時間ポリシーの子プロセスがある場合は予測できないためエラーを返します。
+pub fn summarize(
+    records: &[Record],
+    nproc: usize,
+    setup: &ChildSetup,
+) -> Result<Vec<Share>, String> {
+    let policy_of = |id| {
+        setup
+            .policies
+            .as_ref()
+            .and_then(|p| p.for_child(id))
+            .map_or(Policy::Other, |spec| spec.policy)
+    };
+    if let Some(id) = (0..nproc).find(|&id| policy_of(id).is_realtime()) {
+        return Err(format!(
+            "child {} uses a real-time policy ({}); CFS shares cannot be predicted",
+            id,
+            policy_of(id)
+        ));
+    }
+
+    let mut nice = vec![0; nproc];
+    let mut finished = vec![0; nproc];
+    for record in records {
+        nice[record.id] = record.nice;
+        finished[record.id] = finished[record.id].max(record.time_ms);
+    }
+    let end = finished.iter().copied().min().unwrap_or(0);
+    let mut progressed = vec![0usize; nproc];
+    for record in records.iter().filter(|r| r.time_ms <= end) {
+        progressed[record.id] += 1;
+    }
+
+    let weights: Vec<u32> = (0..nproc)
+        .map(|id| match policy_of(id) {
+            Policy::Idle => WEIGHT_IDLEPRIO,
+            _ => weight(nice[id]),
+        })
+        .collect();
+
+    // 同じ CPU の組を割り当てられた子プロセスどうしで CPU を分け合う
+    let mut groups: BTreeMap<Vec<usize>, Vec<usize>> = BTreeMap::new();
+    for id in 0..nproc {
+        groups
+            .entry(setup.placement.cpus_for(id, &setup.cpus))
+            .or_default()
+            .push(id);
+    }
+    let mut expected_cpu = vec![0.0; nproc];
+    for (cpus, ids) in &groups {
+        let group_weights: Vec<u32> = ids.iter().map(|&id| weights[id]).collect();
+        for (&id, cpu) in ids.iter().zip(expected_cpus(&group_weights, cpus.len())) {
+            expected_cpu[id] = cpu;
+        }
+    }
+
+    let total_expected: f64 = expected_cpu.iter().sum();
+    let total_observed: usize = progressed.iter().sum();
+    Ok((0..nproc)
+        .map(|id| {
+            let expected = expected_cpu[id] / total_expected;
+            let observed = if total_observed == 0 {
+                0.0
+            } else {
+                progressed[id] as f64 / total_observed as f64
+            };
+            Share {
+                id,
+                nice: nice[id],
+                weight: weights[id],
+                expected: round(expected),
+                observed: round(observed),
+                deviation: round(observed - expected),
+            }
+        })
+        .collect())
+}
+
+/// 出力を読みやすくするため小数点以下 4 桁に丸めます。
+fn round(x: f64) -> f64 {
+    (x * 10_000.0).round() / 10_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::affinity::Placement;
+
+    fn setup(cpus: &[usize], placement: Placement, policies: Option<&str>) -> ChildSetup {
+        ChildSetup {
+            cpus: cpus.to_vec(),
+            placement,
+            policies: policies.map(|p| p.parse().unwrap()),
+            nice: None,
+        }
+    }
+
+    /// 子プロセス `id` が `times_ms` の各時刻に記録を残したとみなした記録
+    fn records(id: usize, nice: i32, times_ms: &[usize]) -> Vec<Record> {
+        times_ms
+            .iter()
+            .enumerate()
+            .map(|(i, &time_ms)| Record {
+                id,
+                time_ms,
+                progress: (i + 1) * 100 / times_ms.len(),
+                cputime_ms: 0,
+                cpu: 0,
+                nice,
+                elapsed_nsec: 0,
+                cputime_nsec: 0,
+                begin_nsec: 0,
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(
+            actual.len(),
+            expected.len(),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn weight_follows_kernel_table() {
+        assert_eq!(weight(0), 1024);
+        assert_eq!(weight(5), 335);
+        assert_eq!(weight(-20), 88761);
+        assert_eq!(weight(19), 15);
+        // 範囲外の値は端の値に丸める
+        assert_eq!(weight(-30), 88761);
+        assert_eq!(weight(30), 15);
+    }
+
+    #[test]
+    fn expected_cpus_splits_one_cpu_by_weight() {
+        assert_close(&expected_cpus(&[1024, 1024], 1), &[0.5, 0.5]);
+        assert_close(
+            &expected_cpus(&[1024, 335], 1),
+            &[1024.0 / 1359.0, 335.0 / 1359.0],
+        );
+    }
+
+    #[test]
+    fn expected_cpus_caps_each_process_at_one_cpu() {
+        assert_close(&expected_cpus(&[1024, 1024], 2), &[1.0, 1.0]);
+        assert_close(&expected_cpus(&[1024], 4), &[1.0]);
+        // 重み 4096 の取り分は 2 * 4096 / 6144 > 1 なので 1 に抑え、残りの 1 CPU を等分する
+        assert_close(&expected_cpus(&[1024, 1024, 4096], 2), &[0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn expected_cpus_of_no_process_is_empty() {
+        assert!(expected_cpus(&[], 2).is_empty());
+    }
+
+    #[test]
+    fn summarize_counts_progress_until_the_first_child_finishes() {
+        let mut all = records(0, 0, &[10, 20, 30, 40]);
+        all.extend(records(1, 5, &[20, 40, 60, 80]));
+        let shares = summarize(&all, 2, &setup(&[0], Placement::Shared, None)).unwrap();
+
+        // 子プロセス 0 が終わる 40ms までに、0 は 4 回、1 は 2 回進んでいる
+        let observed: Vec<f64> = shares.iter().map(|s| s.observed).collect();
+        assert_close(&observed, &[0.6667, 0.3333]);
+        let expected: Vec<f64> = shares.iter().map(|s| s.expected).collect();
+        assert_close(&expected, &[round(1024.0 / 1359.0), round(335.0 / 1359.0)]);
+        assert_eq!((shares[0].nice, shares[0].weight), (0, 1024));
+        assert_eq!((shares[1].nice, shares[1].weight), (5, 335));
+        assert_close(&[shares[1].deviation], &[round(2.0 / 6.0 - 335.0 / 1359.0)]);
+    }
+
+    #[test]
+    fn summarize_shares_cpus_within_each_placement_group() {
+        // round-robin では子プロセス 0 と 2 が CPU 0 を、子プロセス 1 が CPU 1 を使う
+        let mut all = records(0, 0, &[20, 40]);
+        all.extend(records(1, 0, &[10, 20, 30, 40]));
+        all.extend(records(2, 0, &[20, 40]));
+        let shares = summarize(&all, 3, &setup(&[0, 1], Placement::RoundRobin, None)).unwrap();
+
+        let expected: Vec<f64> = shares.iter().map(|s| s.expected).collect();
+        assert_close(&expected, &[0.25, 0.5, 0.25]);
+        let observed: Vec<f64> = shares.iter().map(|s| s.observed).collect();
+        assert_close(&observed, &[0.25, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn summarize_gives_idle_policy_the_idle_weight() {
+        let mut all = records(0, 0, &[10, 20]);
+        all.extend(records(1, 0, &[10, 20]));
+        let shares = summarize(&all, 2, &setup(&[0], Placement::Shared, Some("1:idle"))).unwrap();
+        assert_eq!(shares[1].weight, WEIGHT_IDLEPRIO);
+    }
+
+    #[test]
+    fn summarize_rejects_real_time_policies() {
+        let mut all = records(0, 0, &[10, 20]);
+        all.extend(records(1, 0, &[10, 20]));
+        for policy in ["1:fifo:10", "0:rr"] {
+            let setup = setup(&[0], Placement::Shared, Some(policy));
+            assert!(
+                summarize(&all, 2, &setup).is_err(),
+                "{} should be rejected",
+                policy
+            );
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! cgroup v2 の CPU コントローラを使った実験のためのグループの作成と後片付け
+//!
+//! 指定した親ディレクトリの下に `sched-<pid>/g<N>` というグループを作り、
+//! それぞれに `cpu.weight` と `cpu.max` を設定します。作ったグループは [`CgroupSet`] を破棄するときに削除し、
+//! 親ディレクトリの `cgroup.subtree_control` に `cpu` を加えた場合は元に戻します。
+//!
+//! 親ディレクトリで `cpu` コントローラが使えない場合（cgroup v1 に割り当てられている、委譲されていないなど）や、
+//! 書き込めない場合は、グループを作る前にその理由を示すエラーを返します。
+
+use crate::output::Record;
+use nix::unistd::getpid;
+use serde::Serialize;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// `cpu.weight` の既定値
+pub const DEFAULT_WEIGHT: u32 = 100;
+/// `cpu.max` の周期の既定値（μs）
+pub const DEFAULT_PERIOD_USEC: u64 = 100_000;
+
+/// `cpu.max` に設定する上限
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuMax {
+    /// 周期あたりに使える CPU 時間（μs）
+    pub quota_usec: u64,
+    /// 周期（μs）
+    pub period_usec: u64,
+}
+
+impl fmt::Display for CpuMax {
+    /// `--group` で指定する形式（`quota/period`）
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.quota_usec, self.period_usec)
+    }
+}
+
+impl FromStr for CpuMax {
+    type Err = String;
+
+    /// `50000` や `50000/100000` の形式を解釈します。
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (quota, period) = match s.split_once('/') {
+            Some((quota, period)) => (quota, Some(period)),
+            None => (s, None),
+        };
+        let quota_usec = quota
+            .parse()
+            .map_err(|e| format!("max quota {:?} should be number: {}", quota, e))?;
+        let period_usec = match period {
+            Some(period) => period
+                .parse()
+                .map_err(|e| format!("max period {:?} should be number: {}", period, e))?,
+            None => DEFAULT_PERIOD_USEC,
+        };
+        if !(1_000..=1_000_000).contains(&period_usec) {
+            return Err(format!(
+                "max period {} should be between 1000 and 1000000 (us)",
+                period_usec
+            ));
+        }
+        if quota_usec < 1_000 {
+            return Err(format!("max quota {} should be >= 1000 (us)", quota_usec));
+        }
+        Ok(CpuMax {
+            quota_usec,
+            period_usec,
+        })
+    }
+}
+
+/// `--group nproc=2,weight=200,max=50000/100000` のように指定する 1 つのグループ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupSpec {
+    /// グループに入れる子プロセスの数
+    pub nproc: usize,
+    /// `cpu.weight`（1 以上 10000 以下）
+    pub weight: u32,
+    /// `cpu.max`（`None` なら上限なし）
+    pub max: Option<CpuMax>,
+}
+
+impl fmt::Display for GroupSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nproc={},weight={}", self.nproc, self.weight)?;
+        match &self.max {
+            Some(max) => write!(f, ",max={}", max),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for GroupSpec {
+    type Err = String;
+
+    /// `key=value` のカンマ区切りを解釈します。省略した項目は既定値（`nproc=1,weight=100,max=max`）になります。
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spec = GroupSpec {
+            nproc: 1,
+            weight: DEFAULT_WEIGHT,
+            max: None,
+        };
+        for item in s.split(',').filter(|item| !item.is_empty()) {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} should be key=value", item))?;
+            match key {
+                "nproc" => {
+                    spec.nproc = value
+                        .parse()
+                        .map_err(|e| format!("nproc {:?} should be number: {}", value, e))?;
+                    if spec.nproc == 0 {
+                        return Err("nproc should be >= 1".to_string());
+                    }
+                }
+                "weight" => {
+                    spec.weight = value
+                        .parse()
+                        .map_err(|e| format!("weight {:?} should be number: {}", value, e))?;
+                    if !(1..=10_000).contains(&spec.weight) {
+                        return Err(format!(
+                            "weight {} should be between 1 and 10000",
+                            spec.weight
+                        ));
+                    }
+                }
+                "max" if value == "max" => spec.max = None,
+                "max" => spec.max = Some(value.parse()?),
+                _ => {
+                    return Err(format!(
+                        "unknown key {:?} (expected nproc, weight or max)",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// 複数のグループをメタデータに記録する形式（`g0:nproc=1,weight=100;g1:...`）で表します。
+pub fn format_groups(specs: &[GroupSpec]) -> String {
+    specs
+        .iter()
+        .enumerate()
+        .map(|(i, spec)| format!("g{}:{}", i, spec))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// `/proc/self/mountinfo` から cgroup v2 のマウント位置を探します。
+pub fn mount_point() -> Result<PathBuf, String> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|e| format!("/proc/self/mountinfo: read() failed: {}", e))?;
+    mountinfo
+        .lines()
+        .find_map(|line| {
+            // マウントポイントは 5 列目、ファイルシステムの種類は ` - ` の直後
+            let (fields, fs_type) = line.split_once(" - ")?;
+            if fs_type.split_whitespace().next()? != "cgroup2" {
+                return None;
+            }
+            fields.split_whitespace().nth(4).map(PathBuf::from)
+        })
+        .ok_or_else(|| {
+            "cgroup v2 is not mounted (no cgroup2 entry in /proc/self/mountinfo)".to_string()
+        })
+}
+
+/// 書き込みに失敗した理由に、よくある原因の説明を加えます。
+fn explain(path: &Path, op: &str, e: io::Error) -> String {
+    let hint = match e.raw_os_error() {
+        Some(nix::libc::EACCES) | Some(nix::libc::EPERM) | Some(nix::libc::EROFS) => {
+            "; the cgroup is not writable by this user (run as root or pass --cgroup-parent pointing to a delegated cgroup)"
+        }
+        Some(nix::libc::EBUSY) => {
+            "; a cgroup with processes of its own cannot enable controllers for its children (pass --cgroup-parent pointing to an empty cgroup)"
+        }
+        _ => "",
+    };
+    format!("{}: {} failed: {}{}", path.display(), op, e, hint)
+}
+
+/// cgroup v2 のディレクトリ 1 つ
+#[derive(Debug)]
+pub struct Cgroup {
+    /// ディレクトリのパス
+    pub path: PathBuf,
+}
+
+impl Cgroup {
+    fn read(&self, file: &str) -> Result<String, String> {
+        let path = self.path.join(file);
+        fs::read_to_string(&path).map_err(|e| explain(&path, "read()", e))
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<(), String> {
+        let path = self.path.join(file);
+        fs::write(&path, value).map_err(|e| explain(&path, &format!("write({:?})", value), e))
+    }
+
+    /// 子グループで `cpu` コントローラを使えるようにします。
+    ///
+    /// 新たに有効にした場合は `true`、もともと有効だった場合は `false` を返します。
+    fn enable_cpu(&self) -> Result<bool, String> {
+        let controllers = self.read("cgroup.controllers")?;
+        if !controllers.split_whitespace().any(|c| c == "cpu") {
+            return Err(format!(
+                "{}: the cpu controller is not available (cgroup.controllers: {:?}); it may be bound to a cgroup v1 hierarchy or not delegated",
+                self.path.display(),
+                controllers.trim()
+            ));
+        }
+        let enabled = self.read("cgroup.subtree_control")?;
+        if enabled.split_whitespace().any(|c| c == "cpu") {
+            return Ok(false);
+        }
+        self.write("cgroup.subtree_control", "+cpu").map(|()| true)
+    }
+
+    /// [`Cgroup::enable_cpu`] で有効にした `cpu` コントローラを無効に戻します。
+    fn disable_cpu(&self) -> Result<(), String> {
+        self.write("cgroup.subtree_control", "-cpu")
+    }
+
+    /// 呼び出したプロセスをこのグループに移します。
+    pub fn join(&self) -> Result<(), String> {
+        self.write("cgroup.procs", &getpid().to_string())
+    }
+
+    /// `cpu.stat` を読み出します。
+    pub fn cpu_stat(&self) -> Result<CpuStat, String> {
+        let stat = self.read("cpu.stat")?;
+        let field = |key: &str| {
+            stat.lines()
+                .find_map(|line| match line.split_once(' ') {
+                    Some((k, v)) if k == key => v.trim().parse().ok(),
+                    _ => None,
+                })
+                .ok_or_else(|| format!("{}/cpu.stat: missing {}", self.path.display(), key))
+        };
+        Ok(CpuStat {
+            usage_usec: field("usage_usec")?,
+            nr_throttled: field("nr_throttled").unwrap_or(0),
+            throttled_usec: field("throttled_usec").unwrap_or(0),
+        })
+    }
+}
+
+/// `cpu.stat` の値
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuStat {
+    /// グループ全体で使った CPU 時間（μs）
+    pub usage_usec: u64,
+    /// `cpu.max` によって止められた回数
+    pub nr_throttled: u64,
+    /// `cpu.max` によって止められていた時間（μs）
+    pub throttled_usec: u64,
+}
+
+/// 実験のために作ったグループ一式
+///
+/// 破棄するときに、作ったディレクトリをすべて削除します。
+/// 中のプロセスがすべて終了している必要があるので、子プロセスを回収してから破棄してください。
+#[derive(Debug)]
+pub struct CgroupSet {
+    parent: Cgroup,
+    /// `parent` の `cpu` コントローラを新たに有効にしたかどうか
+    parent_enabled: bool,
+    root: Cgroup,
+    specs: Vec<GroupSpec>,
+    /// 子プロセスのグループ（`g0`, `g1`, ...）
+    pub groups: Vec<Cgroup>,
+}
+
+impl CgroupSet {
+    /// `parent` の下にグループを作り、`cpu.weight` と `cpu.max` を設定します。
+    pub fn create(parent: &Path, specs: &[GroupSpec]) -> Result<CgroupSet, String> {
+        let parent = Cgroup {
+            path: parent.to_path_buf(),
+        };
+        if !parent.path.join("cgroup.controllers").exists() {
+            return Err(format!(
+                "{}: not a cgroup v2 directory (no cgroup.controllers)",
+                parent.path.display()
+            ));
+        }
+        let parent_enabled = parent.enable_cpu()?;
+
+        let root = Cgroup {
+            path: parent.path.join(format!("sched-{}", getpid())),
+        };
+        if let Err(e) = fs::create_dir(&root.path) {
+            let e = explain(&root.path, "mkdir()", e);
+            if parent_enabled {
+                if let Err(e) = parent.disable_cpu() {
+                    eprintln!("{}", e);
+                }
+            }
+            return Err(e);
+        }
+        // ここから先で失敗した場合は、破棄するときに作りかけのディレクトリも削除され、親ディレクトリも元に戻る
+        let mut set = CgroupSet {
+            parent,
+            parent_enabled,
+            root,
+            specs: specs.to_vec(),
+            groups: Vec::with_capacity(specs.len()),
+        };
+        set.root.enable_cpu()?;
+        for (i, spec) in specs.iter().enumerate() {
+            let group = Cgroup {
+                path: set.root.path.join(format!("g{}", i)),
+            };
+            fs::create_dir(&group.path).map_err(|e| explain(&group.path, "mkdir()", e))?;
+            set.groups.push(group);
+            let group = &set.groups[i];
+            group.write("cpu.weight", &spec.weight.to_string())?;
+            let max = match &spec.max {
+                Some(max) => format!("{} {}", max.quota_usec, max.period_usec),
+                None => format!("max {}", DEFAULT_PERIOD_USEC),
+            };
+            group.write("cpu.max", &max)?;
+        }
+        Ok(set)
+    }
+
+    /// 子プロセスの総数
+    pub fn nproc(&self) -> usize {
+        self.specs.iter().map(|spec| spec.nproc).sum()
+    }
+
+    /// `id` 番目の子プロセスが入るグループの番号（先頭のグループから順に `nproc` 個ずつ割り当てる）
+    pub fn group_of(&self, id: usize) -> Result<usize, String> {
+        group_of(&self.specs, id)
+    }
+
+    /// `id` 番目の子プロセスとして、呼び出したプロセスをそのグループに移します。
+    pub fn join(&self, id: usize) -> Result<(), String> {
+        self.groups[self.group_of(id)?].join()
+    }
+
+    /// グループごとに、カーネルが記録した CPU 時間をまとめます。
+    pub fn usage(&self) -> Result<Vec<GroupUsage>, String> {
+        let stats = self
+            .groups
+            .iter()
+            .map(Cgroup::cpu_stat)
+            .collect::<Result<Vec<_>, _>>()?;
+        let total: u64 = stats.iter().map(|stat| stat.usage_usec).sum();
+        Ok(self
+            .specs
+            .iter()
+            .zip(stats)
+            .enumerate()
+            .map(|(group, (spec, stat))| GroupUsage {
+                group,
+                nproc: spec.nproc,
+                weight: spec.weight,
+                max: spec.max.map(|max| max.to_string()),
+                usage_ms: stat.usage_usec as f64 / 1000.0,
+                share: round(stat.usage_usec as f64 / total.max(1) as f64),
+                nr_throttled: stat.nr_throttled,
+                throttled_ms: stat.throttled_usec as f64 / 1000.0,
+            })
+            .collect())
+    }
+}
+
+impl Drop for CgroupSet {
+    fn drop(&mut self) {
+        for group in self.groups.iter().rev().chain([&self.root]) {
+            if let Err(e) = fs::remove_dir(&group.path) {
+                eprintln!("{}: rmdir() failed: {}", group.path.display(), e);
+            }
+        }
+        if self.parent_enabled {
+            if let Err(e) = self.parent.disable_cpu() {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+/// `id` 番目の子プロセスが入るグループの番号を返します。
+fn group_of(specs: &[GroupSpec], id: usize) -> Result<usize, String> {
+    let mut first = 0;
+    for (group, spec) in specs.iter().enumerate() {
+        first += spec.nproc;
+        if id < first {
+            return Ok(group);
+        }
+    }
+    Err(format!(
+        "child {} is not in any group (total nproc: {})",
+        id, first
+    ))
+}
+
+/// グループごとの CPU 時間の合計（`cpu.stat` より）
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GroupUsage {
+    /// グループの番号
+    pub group: usize,
+    /// グループ内の子プロセスの数
+    pub nproc: usize,
+    /// `cpu.weight`
+    pub weight: u32,
+    /// `cpu.max`（`quota/period`、上限なしの場合は `None`）
+    pub max: Option<String>,
+    /// グループ全体で使った CPU 時間（ms）
+    pub usage_ms: f64,
+    /// 全グループの合計に占める割合
+    pub share: f64,
+    /// `cpu.max` によって止められた回数
+    pub nr_throttled: u64,
+    /// `cpu.max` によって止められていた時間（ms）
+    pub throttled_ms: f64,
+}
+
+/// ある時間帯にグループが進めた負荷
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GroupShare {
+    /// 時間帯の終わり（ms）
+    pub time_ms: usize,
+    /// グループの番号
+    pub group: usize,
+    /// この時間帯に終えた負荷の合計（ms 分の CPU 時間）
+    pub cpu_ms: usize,
+    /// この時間帯に全グループが終えた負荷に占める割合
+    pub share: f64,
+}
+
+/// 記録を `window_ms` ごとの時間帯に分け、グループごとの負荷の割合を求めます。
+///
+/// 記録 1 つは子プロセスが `resol` ms 分の負荷を終えたことを表します。
+/// どのグループにも入らない子プロセスの記録があればエラーを返します。
+pub fn shares_over_time(
+    records: &[Record],
+    specs: &[GroupSpec],
+    resol: usize,
+    window_ms: usize,
+) -> Result<Vec<GroupShare>, String> {
+    let nwindow = records
+        .iter()
+        .map(|record| record.time_ms / window_ms + 1)
+        .max()
+        .unwrap_or(0);
+    let mut counts = vec![vec![0usize; specs.len()]; nwindow];
+    for record in records {
+        counts[record.time_ms / window_ms][group_of(specs, record.id)?] += 1;
+    }
+    let mut shares = Vec::with_capacity(nwindow * specs.len());
+    for (window, counts) in counts.iter().enumerate() {
+        let total: usize = counts.iter().sum();
+        for (group, &count) in counts.iter().enumerate() {
+            shares.push(GroupShare {
+                time_ms: (window + 1) * window_ms,
+                group,
+                cpu_ms: count * resol,
+                share: round(count as f64 / total.max(1) as f64),
+            });
+        }
+    }
+    Ok(shares)
+}
+
+fn round(x: f64) -> f64 {
+    (x * 10_000.0).round() / 10_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(nproc: usize, weight: u32, max: Option<CpuMax>) -> GroupSpec {
+        GroupSpec { nproc, weight, max }
+    }
+
+    #[test]
+    fn cpu_max_fills_default_period() {
+        assert_eq!(
+            "50000".parse(),
+            Ok(CpuMax {
+                quota_usec: 50_000,
+                period_usec: DEFAULT_PERIOD_USEC
+            })
+        );
+        assert_eq!(
+            "20000/50000".parse(),
+            Ok(CpuMax {
+                quota_usec: 20_000,
+                period_usec: 50_000
+            })
+        );
+    }
+
+    #[test]
+    fn cpu_max_rejects_out_of_range_values() {
+        for s in ["", "x", "500", "50000/999", "50000/1000001", "50000/x"] {
+            assert!(s.parse::<CpuMax>().is_err(), "{:?} should be rejected", s);
+        }
+    }
+
+    #[test]
+    fn group_spec_fills_defaults() {
+        assert_eq!("".parse(), Ok(spec(1, DEFAULT_WEIGHT, None)));
+        assert_eq!("nproc=2".parse(), Ok(spec(2, DEFAULT_WEIGHT, None)));
+        assert_eq!("weight=200,max=max".parse(), Ok(spec(1, 200, None)));
+    }
+
+    #[test]
+    fn group_spec_round_trips() {
+        for s in ["nproc=1,weight=100", "nproc=3,weight=50,max=50000/100000"] {
+            assert_eq!(s.parse::<GroupSpec>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn group_spec_rejects_invalid_items() {
+        for s in [
+            "nproc=0",
+            "nproc=x",
+            "weight=0",
+            "weight=10001",
+            "max=500",
+            "nproc",
+            "cpus=0",
+        ] {
+            assert!(
+                s.parse::<GroupSpec>().is_err(),
+                "{:?} should be rejected",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn group_of_assigns_children_in_order() {
+        let specs = [spec(1, 100, None), spec(2, 100, None), spec(1, 100, None)];
+        let groups: Vec<_> = (0..4).map(|id| group_of(&specs, id)).collect();
+        assert_eq!(groups, [Ok(0), Ok(1), Ok(1), Ok(2)]);
+        assert!(group_of(&specs, 4).is_err());
+        assert!(group_of(&[], 0).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+//! 各実験プログラムとサンプルのコマンドライン引数と処理
+//!
+//! 単独の実験プログラム（`sched` など）とサンプル（`examples/fork.rs` など）、
+//! それらをサブコマンドとしてまとめた `linux-in-practice` は、
+//! いずれもここにある `Args` を解釈して `run()` を呼び出します。
+
+pub mod busy_loop;
+pub mod cache;
+pub mod cow;
+pub mod experiment;
+pub mod filemap;
+pub mod fork;
+pub mod fork_and_exec;
+pub mod io;
+pub mod mmap;
+pub mod plot;
+pub mod ppidloop;
+pub mod sched;
+pub mod sched_cgroup;
+pub mod sched_nice;
+pub mod segv;
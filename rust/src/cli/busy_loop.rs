@@ -0,0 +1,11 @@
+use clap::Parser;
+
+/// 何もせずに CPU を使い続ける（Ctrl + C で止める）
+#[derive(Parser, Debug)]
+pub struct Args {}
+
+// CPU を使い続けること自体が目的なので、空のループにしている
+#[allow(clippy::empty_loop)]
+pub fn run(_: Args) {
+    loop {}
+}
@@ -0,0 +1,106 @@
+use crate::{
+    args::{positive, Repeat},
+    clock::{diff_nsec, get_time},
+    fingerprint::Fingerprint,
+    output::Format,
+    stats::MetricSummary,
+};
+use clap::Parser;
+use core::ffi::c_void;
+use nix::sys::mman::ProtFlags;
+use nix::{
+    libc::EXIT_FAILURE,
+    sys::mman::{mmap, munmap, MapFlags},
+};
+
+const CACHE_LINE_SIZE_BYTE: usize = 64;
+const NLOOP: usize = 4 * 1024 * 1024 * 1024;
+
+/// バッファのサイズを変えながら、1 回のアクセスにかかる時間を測る
+///
+/// 実験した環境の情報をコメント行（`# key: value`）として出力した後、`サイズ(KB), アクセス 1 回あたりの時間(ns)` を出力する。
+/// `--repeat` に 2 以上を指定すると、測定ごとに同じ形式で 1 行ずつ出力した後、時間の要約統計量を
+/// コメント行（`# summary: ...`）として出力する
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// バッファのサイズ（KB単位）
+    #[clap(parse(try_from_str = positive))]
+    size: usize,
+    #[clap(flatten)]
+    repeat: Repeat,
+}
+
+/// バッファ全体にキャッシュラインごとに書き込むことを、合計 `NLOOP` 回繰り返した時間から
+/// 1 回あたりの時間（ns）を返します。
+fn measure(buffer: *mut c_void, size_byte: usize) -> f64 {
+    let before = get_time();
+
+    // for _ in 0..(NLOOP / (size_byte / CACHE_LINE_SIZE_BYTE)) {
+    //     for j in (0..size_byte).step_by(CACHE_LINE_SIZE_BYTE) {
+    //         unsafe {
+    //             buffer
+    //                 .offset(j as isize)
+    //                 .write_bytes(0, CACHE_LINE_SIZE_BYTE);
+    //         }
+    //     }
+    // }
+
+    for _ in 0..(NLOOP / (size_byte / CACHE_LINE_SIZE_BYTE)) {
+        for j in 0..(size_byte / CACHE_LINE_SIZE_BYTE) {
+            unsafe {
+                buffer
+                    .add(j * CACHE_LINE_SIZE_BYTE)
+                    .write_bytes(0, CACHE_LINE_SIZE_BYTE);
+            }
+        }
+    }
+
+    let after = get_time();
+
+    diff_nsec(before, after) as f64 / NLOOP as f64
+}
+
+pub fn run(args: Args) {
+    if let Err(e) = Fingerprint::collect().print() {
+        eprintln!("write() failed: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let size_byte = args.size * 1024;
+
+    let buffer = unsafe {
+        mmap(
+            std::ptr::null_mut(),
+            size_byte,
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    let buffer: *mut c_void = match buffer {
+        Ok(buf) => buf,
+        Err(e) => {
+            eprintln!("mmap() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let times = args.repeat.run(|| measure(buffer, size_byte));
+
+    for time in &times {
+        println!("{}\t{}", args.size, time);
+    }
+    if args.repeat.is_repeated() {
+        let summary = MetricSummary::new(args.size, "time_ns", &times, |&time| time);
+        if let Err(e) = Format::Tsv.write_footer(&mut std::io::stdout(), "summary", &[summary]) {
+            eprintln!("write() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+
+    if let Err(e) = unsafe { munmap(buffer, size_byte) } {
+        eprintln!("mumap() failed: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    };
+}
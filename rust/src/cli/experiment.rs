@@ -0,0 +1,88 @@
+use crate::experiment::Plan;
+use clap::Parser;
+use nix::libc::EXIT_FAILURE;
+use std::{env, path::PathBuf, process::exit};
+
+/// 実験ファイル（TOML）に書いた条件の組み合わせをすべて実行し、条件ごとのディレクトリに結果を書き出す
+///
+/// 記録がすでにある条件は実行しない
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// 実験ファイル
+    plan: PathBuf,
+    /// 実験プログラム（`sched`, `sched_nice`）のあるディレクトリ。省略するとこのプログラムと同じディレクトリ
+    #[clap(long)]
+    bin_dir: Option<PathBuf>,
+    /// 実行せずに、実行するコマンドの一覧だけを表示する
+    #[clap(long)]
+    dry_run: bool,
+    /// 記録がすでにある条件も実行し直す
+    #[clap(long)]
+    force: bool,
+}
+
+/// # コマンドライン引数
+/// 第1引数（plan）: 実験ファイル
+/// `--bin-dir`: 実験プログラムのあるディレクトリ
+/// `--dry-run`: 実行するコマンドの一覧だけを表示する
+/// `--force`: 記録がすでにある条件も実行し直す
+pub fn run(args: Args) {
+    let Args {
+        plan: path,
+        bin_dir,
+        dry_run,
+        force,
+    } = args;
+
+    let plan = match Plan::load(&path) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    // 結果のディレクトリは実験ファイルからの相対パスとする
+    let output = path
+        .parent()
+        .map_or_else(|| plan.output.clone(), |dir| dir.join(&plan.output));
+    let bin_dir = match bin_dir {
+        Some(dir) => dir,
+        None => match env::current_exe() {
+            Ok(exe) => exe.parent().map(PathBuf::from).unwrap_or_default(),
+            Err(e) => {
+                eprintln!("failed to locate the experiment programs: {}", e);
+                exit(EXIT_FAILURE);
+            }
+        },
+    };
+
+    let runs = plan.runs();
+    let (mut done, mut skipped, mut failed) = (0, 0, 0);
+    for (i, run) in runs.iter().enumerate() {
+        let progress = format!("[{}/{}] {}", i + 1, runs.len(), run);
+        if !force && run.records_path(&output).exists() {
+            eprintln!("{}: skipped (already exists)", progress);
+            skipped += 1;
+            continue;
+        }
+        if dry_run {
+            println!("{}", run.command_line(&bin_dir));
+            continue;
+        }
+        eprintln!("{}", progress);
+        match run.execute(&bin_dir, &output) {
+            Ok(()) => done += 1,
+            Err(e) => {
+                eprintln!("{}: {:#}", progress, e);
+                failed += 1;
+            }
+        }
+    }
+
+    if !dry_run {
+        eprintln!("{} done, {} skipped, {} failed", done, skipped, failed);
+    }
+    if failed > 0 {
+        exit(EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,20 @@
+use clap::Parser;
+use nix::unistd::{getpid, ForkResult};
+
+/// `fork()` で子プロセスを作り、親と子がそれぞれ自身のプロセス ID を表示する
+#[derive(Parser, Debug)]
+pub struct Args {}
+
+pub fn run(_: Args) {
+    match unsafe { nix::unistd::fork() } {
+        Ok(ForkResult::Parent { child, .. }) => {
+            println!(
+                "I'm parent! my pid is {} and the pid of my child is {}.",
+                getpid(),
+                child
+            )
+        }
+        Ok(ForkResult::Child) => println!("I'm child! my pid is {}.", getpid()),
+        Err(_) => eprintln!("fork() failed."),
+    };
+}
@@ -0,0 +1,28 @@
+use clap::Parser;
+use nix::unistd::{execve, fork, getpid, ForkResult};
+use std::ffi::CString;
+
+/// `fork()` で作った子プロセスが `execve()` で `/bin/echo hello` に置き換わる
+#[derive(Parser, Debug)]
+pub struct Args {}
+
+pub fn run(_: Args) {
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child, .. }) => {
+            println!(
+                "I'm parent! my pid is {} and the pid of my child is {}.",
+                getpid(),
+                child
+            );
+        }
+        Ok(ForkResult::Child) => {
+            println!("I'm child! my pid is {}.", getpid());
+            let path = CString::new("/bin/echo").unwrap();
+            let args = [&path, &CString::new("hello").unwrap()];
+            // 成功すると戻らない
+            let e = execve(&path, &args, &[CString::new("").unwrap()]).unwrap_err();
+            panic!("execve() failed.: {:?}", e);
+        }
+        Err(_) => eprintln!("fork() failed."),
+    }
+}
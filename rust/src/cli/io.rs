@@ -0,0 +1,257 @@
+//! # 測定内容
+//! - I/O サイズによる性能の変化
+//! - シーケンシャルアクセスとランダムアクセスの違い
+//!
+//! # プログラム仕様
+//! - 指定したパーティションの先頭から1Gバイトまでの領域内に、合計64MバイトのI/Oを発行する
+//! - 読み書きの種類、アクセスパターン（シーケンシャルアクセス、ランダムアクセス）、及び1回あたりのI/Oサイズを指定できる
+//! - 受け取る引数
+//!     - 第1引数: ファイル名
+//!     - 第2引数: 本章の後半において説明する、カーネルによるI/O支援機能を有効にするかどうか（on, off）
+//!     - 第3引数: 読み書きの種類（r = 読み出し、 w = 書き込み）
+//!     - 第4引数: アクセスパターン（seq = シーケンシャルアクセス、 rand = ランダムアクセス）
+//!     - 第5引数: 1回あたりのI/Oサイズ（Kバイト）
+//! - 専用のパーティションがない場合は、`--create` で 1G バイトのテストファイルを作って使える
+//!   （`--loop-device` を加えるとループデバイス経由にする）。どちらも終了時に片付ける
+//! - `--iodepth 1,2,4` のように並列度を指定すると、並列度ごとにその数のスレッドから同時に I/O を発行し、
+//!   並列度によるスループットの変化を比べられる
+
+use crate::{
+    aligned::Alignment,
+    args::{self, positive, Repeat},
+    output::Format,
+    stats::Summary,
+    storage::{
+        self, DepthList, Help, LatencyBucket, Metadata, Op, Pattern, Trial, ACCESS_SIZE, PART_SIZE,
+    },
+    testfile::{LoopDevice, TestFile},
+};
+use clap::Parser;
+use nix::libc::EXIT_FAILURE;
+use serde::Serialize;
+use std::{io, path::PathBuf, process::exit};
+
+/// 指定した領域に一定量の I/O を発行し、スループットと I/O 1 回あたりの時間を出力する
+///
+/// 実験条件を出力した後、並列度と計測ごとに `並列度, 計測番号, I/O回数, バイト数, 経過時間(秒), MB/s, IOPS,
+/// 平均時間(μs), 50/90/99/99.9 パーセンタイル(μs), 最大時間(μs)` を出力する。
+/// `--histogram` を指定すると、続けて計測ごとに I/O 1 回あたりの時間のヒストグラム
+/// `並列度, 計測番号, 区間の下限(ns), 上限(ns), 回数` をコメント行（`jsonl` では `"type": "histogram"`）として出力する。
+/// `--repeat` に 2 以上を指定すると、最後に並列度ごとに各項目の要約統計量をコメント行（`jsonl` では `"type": "summary"`）として出力する
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// I/O を発行するファイル（デバイスファイル、1GB 以上の通常のファイル、または `--create` で作るファイル）
+    filename: PathBuf,
+    /// カーネルによる I/O 支援機能（`off` にすると `O_DIRECT` を使う）
+    #[clap(arg_enum)]
+    help: Help,
+    /// 読み書きの種類
+    #[clap(arg_enum)]
+    op: Op,
+    /// アクセスパターン
+    #[clap(arg_enum)]
+    pattern: Pattern,
+    /// 1 回あたりの I/O サイズ（KB単位）
+    #[clap(parse(try_from_str = positive))]
+    block_size: usize,
+    /// `filename` に 1GB のテストファイルを新しく作って使い、終了時に削除する
+    #[clap(long)]
+    create: bool,
+    /// 作ったテストファイルをループデバイスにつなぎ、ループデバイスに I/O を発行する（`--create` が必要）
+    #[clap(long, requires = "create")]
+    loop_device: bool,
+    /// ランダムアクセスの位置を決める乱数の種
+    #[clap(long, default_value = "1")]
+    seed: u64,
+    /// 同時に I/O を発行するスレッドの数（`1,2,4` のように複数指定すると順に計測する）
+    #[clap(long, default_value = "1")]
+    iodepth: DepthList,
+    /// I/O 1 回あたりの時間のヒストグラムも出力する
+    #[clap(long)]
+    histogram: bool,
+    /// 出力形式
+    #[clap(long, arg_enum, default_value = "tsv")]
+    format: Format,
+    #[clap(flatten)]
+    repeat: Repeat,
+}
+
+/// 1 つの項目の要約統計量
+#[derive(Serialize)]
+struct MetricSummary {
+    iodepth: usize,
+    metric: &'static str,
+    #[serde(flatten)]
+    summary: Summary,
+}
+
+/// # コマンドライン引数
+/// 第1引数（filename）: I/O を発行するファイル
+/// 第2引数（help）: カーネルによる I/O 支援機能（`on` または `off`）
+/// 第3引数（op）: 読み書きの種類（`r` または `w`）
+/// 第4引数（pattern）: アクセスパターン（`seq` または `rand`）
+/// 第5引数（block_size）: 1 回あたりの I/O サイズ（KB単位）
+/// `--create`: `filename` に 1GB のテストファイルを作って使い、終了時に削除する
+/// `--loop-device`: 作ったテストファイルをループデバイスにつないで、ループデバイスに I/O を発行する
+/// `--seed`: ランダムアクセスの位置を決める乱数の種
+/// `--iodepth`: 同時に I/O を発行するスレッドの数（カンマ区切りで複数指定できる）
+/// `--histogram`: I/O 1 回あたりの時間のヒストグラムも出力する
+/// `--format`: 出力形式（`tsv`, `csv` または `jsonl`）
+/// `--repeat`, `--warmup`: 計測を繰り返す回数と、記録する前に捨てる回数
+pub fn run(args: Args) {
+    if let Err(e) = try_run(args) {
+        eprintln!("{}", e);
+        exit(EXIT_FAILURE);
+    }
+}
+
+/// 実験を行います。作ったテストファイルとループデバイスは、エラーで戻る場合も含めて戻る前に片付けます。
+fn try_run(args: Args) -> Result<(), String> {
+    let Args {
+        filename,
+        help,
+        op,
+        pattern,
+        block_size,
+        create,
+        loop_device: attach_loop,
+        seed,
+        iodepth: iodepths,
+        histogram,
+        format,
+        repeat,
+    } = args;
+
+    let block_size = block_size * 1024;
+    if ACCESS_SIZE % block_size != 0 {
+        args::reject(format!(
+            "access size({}) should be multiple of block size: {}",
+            ACCESS_SIZE,
+            block_size / 1024
+        ));
+    }
+
+    let offsets = storage::offsets(pattern, block_size, seed);
+    if let Some(iodepth) = iodepths.0.iter().find(|&&d| d > offsets.len()) {
+        args::reject(format!(
+            "iodepth({}) should not be more than the number of I/Os: {}",
+            iodepth,
+            offsets.len()
+        ));
+    }
+
+    // 変数の宣言と逆の順に破棄されるので、ループデバイスを切り離してからファイルを消す
+    let testfile = if create {
+        eprintln!(
+            "creating {} ({} MB)",
+            filename.display(),
+            PART_SIZE / 1024 / 1024
+        );
+        Some(TestFile::create(&filename, PART_SIZE)?)
+    } else {
+        None
+    };
+    let loop_device = match &testfile {
+        Some(testfile) if attach_loop => Some(LoopDevice::attach(testfile.path())?),
+        _ => None,
+    };
+    let target = loop_device
+        .as_ref()
+        .map_or(filename.as_path(), LoopDevice::path);
+
+    let file = storage::open(target, help)
+        .map_err(|e| format!("{}: open() failed: {}", target.display(), e))?;
+    // 足りないと計測の途中で読み書きに失敗するので、実験条件を出力する前に確かめる
+    let size = storage::size(&file).map_err(|e| format!("{}: {}", target.display(), e))?;
+    if size < PART_SIZE as u64 {
+        return Err(format!(
+            "{}: size ({} bytes) should be at least {} bytes; use --create to make a test file",
+            target.display(),
+            size,
+            PART_SIZE
+        ));
+    }
+    let alignment = Alignment::of(&file).map_err(|e| format!("{}: {}", target.display(), e))?;
+    // 要件を満たさない I/O はカーネルが EINVAL で拒むので、始める前に確かめる
+    if help == Help::Off {
+        alignment
+            .check(block_size, &offsets)
+            .map_err(|e| format!("{}: {}", target.display(), e))?;
+    }
+
+    let mut metadata = Metadata::new(target, help, op, pattern, block_size, seed, &iodepths)
+        .with_alignment(&alignment);
+    if let Some(testfile) = &testfile {
+        metadata = metadata.with_testfile(testfile.path());
+    }
+    if let Some(device) = &loop_device {
+        metadata = metadata.with_loop_device(device);
+    }
+    format
+        .write_header(&mut io::stdout(), &metadata)
+        .map_err(|e| format!("write() failed: {}", e))?;
+
+    // 並列度ごとに、ウォームアップを含めて計測を繰り返す
+    let mut results = Vec::with_capacity(iodepths.0.len());
+    for &iodepth in &iodepths.0 {
+        let latencies = repeat
+            .try_run(|| storage::run(&file, op, &offsets, block_size, alignment.memory, iodepth))
+            .map_err(|e| format!("{}: {:#}", target.display(), e))?;
+        results.push((iodepth, latencies));
+    }
+    let trials: Vec<Trial> = results
+        .iter()
+        .flat_map(|(iodepth, trials)| {
+            trials
+                .iter()
+                .enumerate()
+                .map(|(trial, (elapsed, latencies))| {
+                    Trial::new(*iodepth, trial, block_size, *elapsed, latencies)
+                })
+        })
+        .collect();
+
+    let mut out = io::stdout().lock();
+    format
+        .write_rows(&mut out, "trial", &trials)
+        .map_err(|e| format!("write() failed: {}", e))?;
+    if histogram {
+        let buckets: Vec<LatencyBucket> = results
+            .iter()
+            .flat_map(|(iodepth, trials)| {
+                trials
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(trial, (_, latencies))| {
+                        storage::latency_histogram(*iodepth, trial, latencies)
+                    })
+            })
+            .collect();
+        // 計測ごとの表と混ざらないよう、ヒストグラムは末尾にまとめる
+        format
+            .write_footer(&mut out, "histogram", &buckets)
+            .map_err(|e| format!("write() failed: {}", e))?;
+    }
+    if repeat.is_repeated() {
+        let mut summaries = Vec::new();
+        for &iodepth in &iodepths.0 {
+            let trials: Vec<&Trial> = trials.iter().filter(|t| t.iodepth == iodepth).collect();
+            let summarize = |metric, value: fn(&Trial) -> f64| MetricSummary {
+                iodepth,
+                metric,
+                summary: Summary::new(&trials.iter().map(|t| value(t)).collect::<Vec<_>>()),
+            };
+            summaries.extend([
+                summarize("elapsed_sec", |t| t.elapsed_sec),
+                summarize("mb_per_sec", |t| t.mb_per_sec),
+                summarize("iops", |t| t.iops),
+                summarize("latency_mean_us", |t| t.latency_mean_us),
+                summarize("latency_p99_us", |t| t.latency_p99_us),
+            ]);
+        }
+        format
+            .write_footer(&mut out, "summary", &summaries)
+            .map_err(|e| format!("write() failed: {}", e))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use clap::Parser;
+use nix::unistd::getpid;
+use std::process::Command;
+
+/// 自身のメモリマップ（`/proc/<pid>/maps`）を表示する
+#[derive(Parser, Debug)]
+pub struct Args {}
+
+pub fn run(_: Args) {
+    let pid = getpid();
+    println!("*** memory map before memory allocation ***");
+    let mut cmd = Command::new("cat");
+    cmd.arg(format!("/proc/{}/maps", pid));
+    let output = cmd.output().expect("failed to execute command").stdout;
+    let o = std::str::from_utf8(&output).unwrap();
+    println!("{}", o);
+
+    std::process::exit(nix::libc::EXIT_SUCCESS);
+}
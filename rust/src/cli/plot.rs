@@ -0,0 +1,78 @@
+use crate::plot;
+use clap::Parser;
+use nix::libc::EXIT_FAILURE;
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::exit,
+};
+
+/// `sched` や `sched_nice` の記録を SVG の図にする
+///
+/// 上にプロセス番号と時刻の散布図、下に各プロセスの進捗を描く
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// 記録のファイル（tsv, csv または jsonl）。`-` なら標準入力から読む
+    input: PathBuf,
+    /// SVG の出力先。省略すると入力ファイルの拡張子を `.svg` にしたもの
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// 図のタイトル。省略すると入力ファイル名
+    #[clap(long)]
+    title: Option<String>,
+}
+
+/// # コマンドライン引数
+/// 第1引数（input）: 記録のファイル（`-` なら標準入力）
+/// `-o`, `--output`: SVG の出力先
+/// `--title`: 図のタイトル
+pub fn run(args: Args) {
+    let Args {
+        input,
+        output,
+        title,
+    } = args;
+
+    let stdin = input.as_os_str() == "-";
+    let mut text = String::new();
+    let read = if stdin {
+        io::stdin().read_to_string(&mut text).map(|_| ())
+    } else {
+        fs::read_to_string(&input).map(|t| text = t)
+    };
+    if let Err(e) = read {
+        eprintln!("{}: read() failed: {}", input.display(), e);
+        exit(EXIT_FAILURE);
+    }
+
+    let points = match plot::parse_points(&text) {
+        Ok(points) if points.is_empty() => {
+            eprintln!("{}: no records found", input.display());
+            exit(EXIT_FAILURE);
+        }
+        Ok(points) => points,
+        Err(e) => {
+            eprintln!("{}: {}", input.display(), e);
+            exit(EXIT_FAILURE);
+        }
+    };
+
+    let output = match output {
+        Some(output) => output,
+        None if stdin => {
+            eprintln!("--output is required when reading from stdin");
+            exit(EXIT_FAILURE);
+        }
+        None => input.with_extension("svg"),
+    };
+    let title = title.unwrap_or_else(|| {
+        input
+            .file_stem()
+            .map_or_else(|| "-".into(), |stem| stem.to_string_lossy().into_owned())
+    });
+    if let Err(e) = fs::write(&output, plot::render(&points, &title)) {
+        eprintln!("{}: write() failed: {}", output.display(), e);
+        exit(EXIT_FAILURE);
+    }
+}
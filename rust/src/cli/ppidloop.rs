@@ -0,0 +1,12 @@
+use clap::Parser;
+
+/// `getppid()` を呼び続ける（Ctrl + C で止める）
+#[derive(Parser, Debug)]
+pub struct Args {}
+
+pub fn run(_: Args) {
+    println!("Get PPID. Ctrl + C to stop. ");
+    loop {
+        nix::unistd::getppid();
+    }
+}
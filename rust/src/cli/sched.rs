@@ -0,0 +1,348 @@
+use crate::{
+    affinity::{self, CpuList, Placement},
+    args::{self, positive, Repeat},
+    clock::NSECS_PER_MSEC,
+    latency::{self, NPROC},
+    load::{calibrate, NSAMPLE_FOR_ESTIMATION},
+    output::{Format, Metadata},
+    plot,
+    policy::PolicyList,
+    scheduler::{self, ChildSetup, LoadMode, Workload},
+    stats::MetricSummary,
+    timeslice::{self, SliceSummary},
+};
+use clap::Parser;
+use nix::libc::EXIT_FAILURE;
+use std::{io, path::PathBuf, process::exit};
+
+/// 複数のプロセスを同時に動かし、それぞれの進捗を一定間隔で記録する
+///
+/// 実験条件を出力した後、`プロセス番号, 経過時間(ms), 進捗(%), 消費CPU時間(ms), CPU番号, nice値` の順に記録を出力する。
+/// `--latency` を指定すると、代わりに 2 つのプロセスを交互に起こし合い、起こされてから動き出すまでの待ち時間の分布を出力する。
+/// `--repeat` に 2 以上を指定すると、最後の測定の結果を出力した後、各項目の要約統計量をコメント行（`jsonl` では `"type": "repeat"`）として出力する
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// 同時に動かすプロセス数
+    #[clap(parse(try_from_str = positive), required_unless_present = "latency")]
+    nproc: Option<usize>,
+    /// プログラムを動作させる合計時間（ms単位）
+    #[clap(parse(try_from_str = positive), required_unless_present = "latency")]
+    total: Option<usize>,
+    /// 統計情報の採取間隔（ms単位）
+    #[clap(parse(try_from_str = positive), required_unless_present = "latency")]
+    resol: Option<usize>,
+    /// 負荷のかけ方（loops: 推定したループ回数, cputime: 自スレッドのCPU時間）
+    #[clap(long, arg_enum, default_value = "loops")]
+    mode: LoadMode,
+    /// 出力形式
+    #[clap(long, arg_enum, default_value = "tsv")]
+    format: Format,
+    /// 動作させる CPU（`0,2-3` 形式）。省略すると現在動作可能な CPU すべて
+    #[clap(long)]
+    cpus: Option<CpuList>,
+    /// 子プロセスへの CPU の割り当て方
+    #[clap(long, arg_enum, default_value = "shared")]
+    placement: Placement,
+    /// 子プロセスごとのスケジューリングポリシーと優先度（例: `0:fifo:10,1:other`）
+    #[clap(long)]
+    policy: Option<PolicyList>,
+    /// 子プロセスの代わりに、1 つのプロセス内のスレッドで負荷をかける
+    #[clap(long)]
+    threads: bool,
+    /// 記録を図にした SVG ファイルの出力先
+    #[clap(long)]
+    svg: Option<PathBuf>,
+    /// 記録の後に、カーネルが記録した子プロセスごとのスケジューラの統計情報を出力する
+    #[clap(long)]
+    schedstat: bool,
+    /// 記録の代わりに、記録から求めたタイムスライスと待ち時間のヒストグラムを出力する
+    #[clap(long)]
+    histogram: bool,
+    /// `--histogram` で待たされたとみなす、採取間隔あたりの待ち時間の下限（μs単位）
+    #[clap(long, default_value = "100")]
+    gap_threshold: u64,
+    /// 記録の代わりに、2 つのプロセスを指定した回数だけ往復させて起床レイテンシを計る
+    #[clap(
+        long,
+        value_name = "ROUNDS",
+        parse(try_from_str = positive),
+        conflicts_with_all = &["nproc", "total", "resol", "threads", "svg", "schedstat", "histogram"]
+    )]
+    latency: Option<usize>,
+    /// `--latency` で記録する前に捨てる往復の回数
+    #[clap(long, default_value = "100", requires = "latency")]
+    latency_warmup: usize,
+    /// `--latency` で、分布の後に 1 回ごとの待ち時間も出力する
+    #[clap(long, requires = "latency")]
+    samples: bool,
+    #[clap(flatten)]
+    repeat: Repeat,
+}
+
+/// # コマンドライン引数
+/// 第1引数（nproc）: 同時に動かすプロセス数
+/// 第2引数（total）: プログラムを動作させる合計時間（ms単位）
+/// 第3引数（resol）: 統計情報の採取間隔（ms単位）
+/// `--mode`: 負荷のかけ方（`loops` または `cputime`）
+/// `--format`: 出力形式（`tsv`, `csv` または `jsonl`）
+/// `--cpus`: 動作させる CPU（`taskset -c` と同じ形式）
+/// `--placement`: 子プロセスへの CPU の割り当て方（`shared` または `round-robin`）
+/// `--policy`: 子プロセスごとのスケジューリングポリシー（`<id>:<policy>[:<priority>]` のカンマ区切り）
+/// `--threads`: 子プロセスの代わりにスレッドで負荷をかける
+/// `--svg`: 記録を図にした SVG ファイルの出力先
+/// `--schedstat`: 子プロセスごとのスケジューラの統計情報も出力する
+/// `--histogram`: 記録の代わりにタイムスライスと待ち時間のヒストグラムを出力する
+/// `--gap-threshold`: 待たされたとみなす待ち時間の下限（μs単位）
+/// `--latency`: 記録の代わりに、指定した回数の往復で起床レイテンシを計る
+/// `--latency-warmup`: 起床レイテンシを記録する前に捨てる往復の回数
+/// `--samples`: 起床レイテンシの 1 回ごとの待ち時間も出力する
+/// `--repeat`, `--warmup`: 測定を繰り返す回数と、記録する前に捨てる回数
+///
+/// 起床レイテンシは、同じ CPU で切り替わる場合は `--cpus 0`、
+/// 別々の CPU を起こす場合は `--cpus 0,1 --placement round-robin` のように指定して計る
+pub fn run(args: Args) {
+    if let Some(rounds) = args.latency {
+        return run_latency(rounds, args);
+    }
+    let Args {
+        nproc,
+        total,
+        resol,
+        mode,
+        format,
+        cpus,
+        placement,
+        policy,
+        threads,
+        svg,
+        schedstat,
+        histogram,
+        gap_threshold,
+        repeat,
+        ..
+    } = args;
+    // `--latency` がなければ clap が 3 つとも要求するが、設定に頼らず引数の誤りとして扱う
+    let (nproc, total, resol) = match (nproc, total, resol) {
+        (Some(nproc), Some(total), Some(resol)) => (nproc, total, resol),
+        _ => args::reject("<NPROC>, <TOTAL> and <RESOL> are required unless --latency is given"),
+    };
+
+    if total % resol != 0 {
+        args::reject(format!(
+            "<total>({}) should be multiple of <resolution>({})",
+            total, resol
+        ));
+    }
+    let nrecord = total / resol;
+
+    let cpus = match affinity::restrict(cpus.as_ref()) {
+        Ok(cpus) => cpus,
+        Err(e) => {
+            eprintln!("sched_setaffinity() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    if let Some(policy) = &policy {
+        if let Err(e) = policy.check(nproc) {
+            args::reject(e);
+        }
+    }
+    let setup = ChildSetup {
+        cpus,
+        placement,
+        policies: policy.clone(),
+        nice: None,
+    };
+
+    let calibration = match mode {
+        LoadMode::Loops => {
+            let calibration = calibrate(NSAMPLE_FOR_ESTIMATION);
+            eprintln!("calibration: {}", calibration);
+            Some(calibration)
+        }
+        LoadMode::Cputime => None,
+    };
+    let workload = match &calibration {
+        Some(calibration) => Workload::Loops(calibration.loops_per_msec() * resol),
+        None => Workload::Cputime(resol * NSECS_PER_MSEC),
+    };
+
+    let mut metadata = Metadata::new("sched", nproc, total, resol, mode).with_placement(placement);
+    if let Some(calibration) = &calibration {
+        metadata = metadata.with_calibration(calibration);
+    }
+    if let Some(policy) = &policy {
+        metadata = metadata.with_policies(policy);
+    }
+    if threads {
+        metadata = metadata.with_threads();
+    }
+    if let Err(e) = format.write_header(&mut io::stdout(), &metadata) {
+        eprintln!("write() failed: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let setup = |id| setup.apply(id);
+    let timelines = repeat.try_run(|| {
+        if threads {
+            scheduler::run_threads(nproc, workload, nrecord, schedstat, setup)
+        } else {
+            scheduler::run(nproc, workload, nrecord, schedstat, setup)
+        }
+    });
+    let timelines = match timelines {
+        Ok(timelines) => timelines,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    // 記録は最後の測定のものを出力する
+    let timeline = timelines.last().expect("--repeat should be >= 1");
+    // 子プロセスとスレッドで、作成にかかる時間を比べられるようにする
+    eprintln!(
+        "created {} {} in {:.3} ms ({:.1} us each)",
+        nproc,
+        if threads { "threads" } else { "processes" },
+        timeline.creation_nsec as f64 / NSECS_PER_MSEC as f64,
+        timeline.creation_nsec as f64 / nproc as f64 / 1000.0
+    );
+
+    if let Some(path) = &svg {
+        let title = format!("sched: nproc={} total={}ms resol={}ms", nproc, total, resol);
+        if let Err(e) = plot::write_svg(path, &timeline.records, &title) {
+            eprintln!("{}: write() failed: {}", path.display(), e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+
+    if histogram {
+        // 連続して動作した時間と待たされた時間に分け、それぞれの分布を出力する
+        let slices = timeslice::split(&timeline.records, nproc, gap_threshold * 1000);
+        let runs: Vec<u64> = slices.iter().flat_map(|s| s.runs.iter().copied()).collect();
+        let waits: Vec<u64> = slices
+            .iter()
+            .flat_map(|s| s.waits.iter().copied())
+            .collect();
+        let mut buckets = timeslice::histogram("timeslice", &runs);
+        buckets.extend(timeslice::histogram("wait", &waits));
+        let summaries: Vec<SliceSummary> = slices.iter().map(SliceSummary::from).collect();
+        let mut out = io::stdout().lock();
+        // 子プロセスごとの要約は、ヒストグラムの表と混ざらないように末尾にまとめる
+        if let Err(e) = format
+            .write_rows(&mut out, "histogram", &buckets)
+            .and_then(|()| format.write_footer(&mut out, "slices", &summaries))
+        {
+            eprintln!("write() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    } else if let Err(e) = format.write_records(&mut io::stdout().lock(), &timeline.records) {
+        eprintln!("write() failed: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    }
+    if schedstat {
+        if let Err(e) =
+            format.write_footer(&mut io::stdout().lock(), "schedstat", &timeline.schedstats)
+        {
+            eprintln!("write() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+    if repeat.is_repeated() {
+        let msec = |nsec: usize| nsec as f64 / NSECS_PER_MSEC as f64;
+        let mut summaries = vec![MetricSummary::new("all", "creation_ms", &timelines, |t| {
+            msec(t.creation_nsec)
+        })];
+        summaries.extend(
+            (0..nproc)
+                .map(|id| MetricSummary::new(id, "finish_ms", &timelines, |t| t.finish_msec(id))),
+        );
+        if let Err(e) = format.write_footer(&mut io::stdout().lock(), "repeat", &summaries) {
+            eprintln!("write() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// 2 つのプロセスを `rounds` 回往復させ、向きごとの起床レイテンシの分布を出力します。
+fn run_latency(rounds: usize, args: Args) {
+    let Args {
+        latency_warmup: warmup,
+        samples,
+        format,
+        cpus,
+        placement,
+        policy,
+        repeat,
+        ..
+    } = args;
+    let cpus = match affinity::restrict(cpus.as_ref()) {
+        Ok(cpus) => cpus,
+        Err(e) => {
+            eprintln!("sched_setaffinity() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    if let Some(policy) = &policy {
+        if let Err(e) = policy.check(NPROC) {
+            args::reject(e);
+        }
+    }
+    let mut metadata = Metadata::latency("sched", rounds, warmup).with_placement(placement);
+    if let Some(policy) = &policy {
+        metadata = metadata.with_policies(policy);
+    }
+    let setup = ChildSetup {
+        cpus,
+        placement,
+        policies: policy,
+        nice: None,
+    };
+
+    if let Err(e) = format.write_header(&mut io::stdout(), &metadata) {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+
+    let trials = match repeat.try_run(|| latency::run(rounds, warmup, |id| setup.apply(id))) {
+        Ok(trials) => trials,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    let summaries: Vec<_> = trials
+        .iter()
+        .map(|measured| latency::summarize(measured))
+        .collect();
+    // 分布は最後の測定のものを出力する
+    let measured = trials.last().expect("--repeat should be >= 1");
+
+    let mut out = io::stdout().lock();
+    if let Err(e) = format.write_rows(&mut out, "summary", &summaries[summaries.len() - 1]) {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+    if samples {
+        if let Err(e) = format.write_footer(&mut out, "sample", measured) {
+            eprintln!("write() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    }
+    if repeat.is_repeated() {
+        // 向きの並びはどの測定でも同じ
+        let mut repeated = Vec::new();
+        for (i, summary) in summaries[0].iter().enumerate() {
+            let direction = &summary.direction;
+            repeated.extend([
+                MetricSummary::new(direction, "mean_ns", &summaries, |s| s[i].mean_ns as f64),
+                MetricSummary::new(direction, "p50_ns", &summaries, |s| s[i].p50_ns as f64),
+                MetricSummary::new(direction, "p99_ns", &summaries, |s| s[i].p99_ns as f64),
+            ]);
+        }
+        if let Err(e) = format.write_footer(&mut out, "repeat", &repeated) {
+            eprintln!("write() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{
+    affinity::{self, CpuList, Placement},
+    args::{self, positive, Repeat},
+    cgroup::{self, CgroupSet, GroupSpec},
+    load::{calibrate, NSAMPLE_FOR_ESTIMATION},
+    output::{Format, Metadata},
+    scheduler::{self, ChildSetup, LoadMode, Workload},
+    stats::MetricSummary,
+};
+use clap::Parser;
+use nix::libc::EXIT_FAILURE;
+use std::{io, path::PathBuf, process::exit};
+
+/// 子プロセスを cgroup v2 のグループに分けて同時に動かし、グループごとの CPU 時間の取り分の推移を出力する
+///
+/// 実験条件を出力した後、`時間帯の終わり(ms), グループ番号, 終えた負荷(ms), 割合` の順に出力し、
+/// 最後に各グループの `cpu.stat` をコメント行（`jsonl` では `"type": "cgroup"`）として出力する。
+/// `--repeat` に 2 以上を指定すると、最後の測定の結果を出力した後、グループごとの CPU 時間と取り分の要約統計量を
+/// コメント行（`jsonl` では `"type": "repeat"`）として出力する
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// プログラムを動作させる合計時間（ms単位）
+    #[clap(parse(try_from_str = positive))]
+    total: usize,
+    /// 統計情報の採取間隔（ms単位）
+    #[clap(parse(try_from_str = positive))]
+    resol: usize,
+    /// グループの設定（例: `nproc=2,weight=200,max=50000/100000`）。グループの数だけ繰り返し指定する
+    #[clap(long = "group", required = true, multiple_occurrences = true)]
+    groups: Vec<GroupSpec>,
+    /// グループを作る親の cgroup。省略すると cgroup v2 のマウント位置
+    #[clap(long)]
+    cgroup_parent: Option<PathBuf>,
+    /// 取り分を集計する時間帯の幅（ms単位）。省略すると採取間隔の 10 倍
+    #[clap(long, parse(try_from_str = positive))]
+    window: Option<usize>,
+    /// 出力形式
+    #[clap(long, arg_enum, default_value = "tsv")]
+    format: Format,
+    /// 動作させる CPU（`0,2-3` 形式）。省略すると現在動作可能な CPU すべて
+    #[clap(long)]
+    cpus: Option<CpuList>,
+    /// 子プロセスへの CPU の割り当て方
+    #[clap(long, arg_enum, default_value = "shared")]
+    placement: Placement,
+    #[clap(flatten)]
+    repeat: Repeat,
+}
+
+/// # コマンドライン引数
+/// 第1引数（total）: プログラムを動作させる合計時間（ms単位）
+/// 第2引数（resol）: 統計情報の採取間隔（ms単位）
+/// `--group`: グループの設定（`nproc=<N>,weight=<1-10000>,max=<quota>[/<period>]`、省略した項目は `nproc=1,weight=100,max=max`）
+/// `--cgroup-parent`: グループを作る親の cgroup（`cpu` コントローラが使え、書き込める必要がある）
+/// `--window`: 取り分を集計する時間帯の幅（ms単位）
+/// `--format`: 出力形式（`tsv`, `csv` または `jsonl`）
+/// `--cpus`: 動作させる CPU（`taskset -c` と同じ形式）
+/// `--placement`: 子プロセスへの CPU の割り当て方（`shared` または `round-robin`）
+/// `--repeat`, `--warmup`: 測定を繰り返す回数と、記録する前に捨てる回数
+///
+/// グループは測定ごとに `<cgroup-parent>/sched-<pid>/g<N>` に作り、測定を終えるたびに削除する（`SIGINT` などで中断した場合は残る）
+pub fn run(args: Args) {
+    let Args {
+        total,
+        resol,
+        groups,
+        cgroup_parent,
+        window,
+        format,
+        cpus,
+        placement,
+        repeat,
+    } = args;
+
+    if total % resol != 0 {
+        args::reject(format!(
+            "<total>({}) should be multiple of <resolution>({})",
+            total, resol
+        ));
+    }
+    let nrecord = total / resol;
+
+    let cpus = match affinity::restrict(cpus.as_ref()) {
+        Ok(cpus) => cpus,
+        Err(e) => {
+            eprintln!("sched_setaffinity() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    let parent = match cgroup_parent.map_or_else(cgroup::mount_point, Ok) {
+        Ok(parent) => parent,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    let nproc = groups.iter().map(|spec| spec.nproc).sum();
+    let setup = ChildSetup {
+        cpus,
+        placement,
+        policies: None,
+        nice: None,
+    };
+
+    let calibration = calibrate(NSAMPLE_FOR_ESTIMATION);
+    eprintln!("calibration: {}", calibration);
+    let workload = Workload::Loops(calibration.loops_per_msec() * resol);
+
+    let metadata = Metadata::new("sched_cgroup", nproc, total, resol, LoadMode::Loops)
+        .with_calibration(&calibration)
+        .with_placement(placement)
+        .with_cgroups(&groups);
+    if let Err(e) = format.write_header(&mut io::stdout(), &metadata) {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+
+    // `cpu.stat` がその測定の分だけになるよう、測定ごとにグループを作り直す。
+    // グループはクロージャを抜けるときに、エラーで抜ける場合も含めて削除される
+    let trials = repeat.try_run(|| {
+        let cgroups = CgroupSet::create(&parent, &groups)?;
+        let timeline = scheduler::run(nproc, workload, nrecord, false, |id| {
+            // 負荷をかけ始める前にグループに入る。入れなかった子プロセスは異常終了し、回収するときにエラーになる
+            cgroups.join(id)?;
+            setup.apply(id)
+        })
+        .map_err(|e| format!("{:#}", e))?;
+        let usage = cgroups.usage()?;
+        Ok::<_, String>((timeline, usage))
+    });
+    let trials = match trials {
+        Ok(trials) => trials,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    // 推移と `cpu.stat` は最後の測定のものを出力する
+    let (timeline, usage) = trials.last().expect("--repeat should be >= 1");
+
+    let shares = match cgroup::shares_over_time(
+        &timeline.records,
+        &groups,
+        resol,
+        window.unwrap_or(resol * 10),
+    ) {
+        Ok(shares) => shares,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    let mut out = io::stdout().lock();
+    if let Err(e) = format
+        .write_rows(&mut out, "share", &shares)
+        .and_then(|()| format.write_footer(&mut out, "cgroup", usage))
+    {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+    if repeat.is_repeated() {
+        let mut summaries = Vec::new();
+        for group in 0..groups.len() {
+            summaries.extend([
+                MetricSummary::new(group, "usage_ms", &trials, |(_, usage)| {
+                    usage[group].usage_ms
+                }),
+                MetricSummary::new(group, "share", &trials, |(_, usage)| usage[group].share),
+            ]);
+        }
+        if let Err(e) = format.write_footer(&mut out, "repeat", &summaries) {
+            eprintln!("write() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    affinity::{self, CpuList, Placement},
+    args::{self, positive, Repeat},
+    cfs,
+    load::{calibrate, NSAMPLE_FOR_ESTIMATION},
+    nice::NiceList,
+    output::{Format, Metadata},
+    plot,
+    policy::PolicyList,
+    scheduler::{self, ChildSetup, LoadMode, Workload},
+    stats::MetricSummary,
+};
+use clap::Parser;
+use nix::libc::EXIT_FAILURE;
+use std::{io, path::PathBuf, process::exit};
+
+/// nice 値の異なる複数のプロセスを同時に動かし、それぞれの進捗を一定間隔で記録する
+///
+/// 実験条件を出力した後、`プロセス番号, 経過時間(ms), 進捗(%), 消費CPU時間(ms), CPU番号, nice値` の順に記録を出力する。
+/// `--repeat` に 2 以上を指定すると、最後の測定の結果を出力した後、子プロセスごとに負荷をかけ終えるまでの時間と
+/// （`--summary` を指定した場合は）実測した取り分の要約統計量をコメント行（`jsonl` では `"type": "repeat"`）として出力する
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// 同時に動かすプロセス数
+    #[clap(long, parse(try_from_str = positive), default_value = "2")]
+    nproc: usize,
+    /// 子プロセスごとの nice 値（先頭の子プロセスから順に指定する）
+    #[clap(long, allow_hyphen_values = true, default_value = "5")]
+    nice: NiceList,
+    /// プログラムを動作させる合計時間（ms単位）
+    #[clap(parse(try_from_str = positive))]
+    total: usize,
+    /// 統計情報の採取間隔（ms単位）
+    #[clap(parse(try_from_str = positive))]
+    resol: usize,
+    /// 出力形式
+    #[clap(long, arg_enum, default_value = "tsv")]
+    format: Format,
+    /// 動作させる CPU（`0,2-3` 形式）。省略すると現在動作可能な CPU すべて
+    #[clap(long)]
+    cpus: Option<CpuList>,
+    /// 子プロセスへの CPU の割り当て方
+    #[clap(long, arg_enum, default_value = "shared")]
+    placement: Placement,
+    /// 子プロセスごとのスケジューリングポリシーと優先度（例: `0:fifo:10,1:other`）
+    #[clap(long)]
+    policy: Option<PolicyList>,
+    /// 記録を図にした SVG ファイルの出力先
+    #[clap(long)]
+    svg: Option<PathBuf>,
+    /// 記録の後に、カーネルが記録した子プロセスごとのスケジューラの統計情報を出力する
+    #[clap(long)]
+    schedstat: bool,
+    /// 記録の代わりに、子プロセスごとの CPU 時間の取り分を nice 値から予測した値と比較して出力する
+    #[clap(long)]
+    summary: bool,
+    /// `--summary` で予測値との差として許容する値（パーセントポイント）。超えた場合は失敗として終了する
+    #[clap(long, default_value = "5")]
+    tolerance: f64,
+    #[clap(flatten)]
+    repeat: Repeat,
+}
+
+/// ## コマンドライン引数
+/// - 第1引数（total）: プログラムを動作させる合計時間（ms単位）
+/// - 第2引数（resol）: 統計情報の採取間隔（ms単位）
+/// - `--nproc`: 同時に動かすプロセス数（既定値は 2）
+/// - `--nice`: 子プロセスごとの nice 値（既定値は最初の子プロセスだけ 5）
+/// - `--format`: 出力形式（`tsv`, `csv` または `jsonl`）
+/// - `--cpus`: 動作させる CPU（`taskset -c` と同じ形式）
+/// - `--placement`: 子プロセスへの CPU の割り当て方（`shared` または `round-robin`）
+/// - `--policy`: 子プロセスごとのスケジューリングポリシー（`<id>:<policy>[:<priority>]` のカンマ区切り）
+/// - `--svg`: 記録を図にした SVG ファイルの出力先
+/// - `--schedstat`: 子プロセスごとのスケジューラの統計情報も出力する
+/// - `--summary`: CPU 時間の取り分の予測値と実測値を比較して出力する
+/// - `--tolerance`: `--summary` で許容する予測値との差（パーセントポイント）
+/// - `--repeat`, `--warmup`: 測定を繰り返す回数と、記録する前に捨てる回数
+pub fn run(args: Args) {
+    // プログラムを動作させる合計時間(ms)と統計情報の採取時間(ms)
+    let Args {
+        nproc,
+        nice,
+        total,
+        resol,
+        format,
+        cpus,
+        placement,
+        policy,
+        svg,
+        schedstat,
+        summary,
+        tolerance,
+        repeat,
+    } = args;
+
+    // プログラムを動作させる合計時間が統計情報の採取時間(resol: 解像度)で割り切れるかを確認
+    if total % resol != 0 {
+        args::reject(format!(
+            "<total>({}) should be multiple of <resolution>({})",
+            total, resol
+        ));
+    }
+    // 計測するレコード数を計算する
+    // 100ms を 10ms 単位で計測する場合は、10 レコードとなる
+    let nrecord = total / resol;
+
+    let cpus = match affinity::restrict(cpus.as_ref()) {
+        Ok(cpus) => cpus,
+        Err(e) => {
+            eprintln!("sched_setaffinity() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    if let Some(policy) = &policy {
+        if let Err(e) = policy.check(nproc) {
+            args::reject(e);
+        }
+    }
+    if let Err(e) = nice.check(nproc) {
+        args::reject(e);
+    }
+    let setup = ChildSetup {
+        cpus,
+        placement,
+        policies: policy.clone(),
+        nice: Some(nice.clone()),
+    };
+
+    // 1ms にかかるループ回数を計測し、それを解像度(ms)に合わせる
+    let calibration = calibrate(NSAMPLE_FOR_ESTIMATION);
+    eprintln!("calibration: {}", calibration);
+    let workload = Workload::Loops(calibration.loops_per_msec() * resol);
+
+    let mut metadata = Metadata::new("sched_nice", nproc, total, resol, LoadMode::Loops)
+        .with_calibration(&calibration)
+        .with_placement(placement)
+        .with_nice(&nice);
+    if let Some(policy) = &policy {
+        metadata = metadata.with_policies(policy);
+    }
+    if let Err(e) = format.write_header(&mut io::stdout(), &metadata) {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+
+    let timelines = match repeat
+        .try_run(|| scheduler::run(nproc, workload, nrecord, schedstat, |id| setup.apply(id)))
+    {
+        Ok(timelines) => timelines,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    // 記録は最後の測定のものを出力する
+    let timeline = timelines.last().expect("--repeat should be >= 1");
+    let mut repeated: Vec<MetricSummary> = (0..nproc)
+        .map(|id| MetricSummary::new(id, "finish_ms", &timelines, |t| t.finish_msec(id)))
+        .collect();
+
+    if let Some(path) = &svg {
+        let title = format!(
+            "sched_nice: nproc={} nice={} total={}ms resol={}ms",
+            nproc, nice, total, resol
+        );
+        if let Err(e) = plot::write_svg(path, &timeline.records, &title) {
+            eprintln!("{}: write() failed: {}", path.display(), e);
+            exit(EXIT_FAILURE);
+        }
+    }
+
+    if !summary {
+        if let Err(e) = format.write_records(&mut io::stdout().lock(), &timeline.records) {
+            eprintln!("write() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+        if schedstat {
+            if let Err(e) =
+                format.write_footer(&mut io::stdout().lock(), "schedstat", &timeline.schedstats)
+            {
+                eprintln!("write() failed: {}", e);
+                exit(EXIT_FAILURE);
+            }
+        }
+        write_repeated(format, &repeat, &repeated);
+        return;
+    }
+
+    // 全員が CPU を取り合っている間の取り分を、nice 値の重みから予測した取り分と比べる
+    let trials = match timelines
+        .iter()
+        .map(|timeline| cfs::summarize(&timeline.records, nproc, &setup))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(trials) => trials,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(EXIT_FAILURE);
+        }
+    };
+    repeated.extend(
+        (0..nproc)
+            .map(|id| MetricSummary::new(id, "observed", &trials, |shares| shares[id].observed)),
+    );
+    let shares = trials.last().expect("--repeat should be >= 1");
+    if let Err(e) = format.write_rows(&mut io::stdout().lock(), "summary", shares) {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+    if schedstat {
+        if let Err(e) =
+            format.write_footer(&mut io::stdout().lock(), "schedstat", &timeline.schedstats)
+        {
+            eprintln!("write() failed: {}", e);
+            exit(EXIT_FAILURE);
+        }
+    }
+    write_repeated(format, &repeat, &repeated);
+    let outliers: Vec<_> = shares
+        .iter()
+        .filter(|share| share.deviation.abs() * 100.0 > tolerance)
+        .collect();
+    for share in &outliers {
+        eprintln!(
+            "child {} (nice {}): observed share {:.1}% deviates from expected {:.1}% by more than {}pt",
+            share.id,
+            share.nice,
+            share.observed * 100.0,
+            share.expected * 100.0,
+            tolerance
+        );
+    }
+    if !outliers.is_empty() {
+        exit(EXIT_FAILURE);
+    }
+}
+
+/// 繰り返し測定した場合に、各項目の要約統計量を出力します。
+fn write_repeated(format: Format, repeat: &Repeat, summaries: &[MetricSummary]) {
+    if !repeat.is_repeated() {
+        return;
+    }
+    if let Err(e) = format.write_footer(&mut io::stdout().lock(), "repeat", summaries) {
+        eprintln!("write() failed: {}", e);
+        exit(EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,15 @@
+use clap::Parser;
+
+/// 不正なアドレス（ヌルポインタ）に書き込み、SIGSEGV で強制終了される
+#[derive(Parser, Debug)]
+pub struct Args {}
+
+pub fn run(_: Args) {
+    let p = std::ptr::null_mut::<i32>();
+    println!("before invalid access");
+    unsafe {
+        p.write(0);
+    }
+    println!("after invalid access");
+    std::process::exit(nix::libc::EXIT_SUCCESS);
+}
@@ -0,0 +1,50 @@
+//! 時刻の取得と差分の計算
+
+use nix::{
+    libc::EXIT_FAILURE,
+    sys::time::{TimeSpec, TimeValLike},
+    time::{clock_gettime, ClockId},
+};
+
+pub const NSECS_PER_MSEC: usize = 1_000_000;
+pub const NSECS_PER_SEC: usize = 1_000_000_000;
+
+/// 指定したクロックの現在値を取得します。
+/// 取得に失敗した場合は、エラーを表示し終了します。
+#[inline]
+pub fn gettime(clock: ClockId) -> TimeSpec {
+    match clock_gettime(clock) {
+        Ok(time) => time,
+        Err(e) => {
+            eprintln!("clock_gettime({}) failed: {}", clock, e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// `CLOCK_MONOTONIC` で現在時刻を取得します。
+#[inline]
+pub fn get_time() -> TimeSpec {
+    gettime(ClockId::CLOCK_MONOTONIC)
+}
+
+/// `CLOCK_THREAD_CPUTIME_ID` で呼び出したスレッドが消費した CPU 時間を取得します。
+#[inline]
+pub fn get_cputime() -> TimeSpec {
+    gettime(ClockId::CLOCK_THREAD_CPUTIME_ID)
+}
+
+/// 2つの `TimeSpec` の差分を計算しナノ秒で返却します。
+///
+/// 秒とナノ秒を別々に引くと繰り下がりを考慮する必要があるため、
+/// `TimeSpec` 同士で引き算してからナノ秒に変換します。
+/// `after` が `before` より前の場合は 0 を返します。
+#[inline]
+pub fn diff_nsec(before: TimeSpec, after: TimeSpec) -> usize {
+    let diff = (after - before).num_nanoseconds();
+    if diff < 0 {
+        0
+    } else {
+        diff as usize
+    }
+}
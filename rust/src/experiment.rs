@@ -0,0 +1,462 @@
+//! 実験ファイル（TOML）に書いた条件の組み合わせを実行する
+//!
+//! 実験ファイルには `[[experiment]]` ごとに実験プログラムと条件を書きます。
+//! 条件には値を 1 つだけ書くことも、配列で複数書くこともでき、配列で書いた条件はすべての組み合わせを実行します。
+//!
+//! ```toml
+//! # 結果を書き出すディレクトリ（実験ファイルからの相対パス）
+//! output = "results"
+//!
+//! [[experiment]]
+//! name = "1core"
+//! program = "sched"
+//! nproc = [1, 2, 4]
+//! cpus = "0"
+//! total = 100
+//! resol = 1
+//! ```
+//!
+//! 結果は `<output>/<name>/<条件>/` に、記録（`records.<format>`）、実行したコマンド（`command.txt`）、
+//! 標準エラー出力（`stderr.txt`）として書き出します。記録がすでにあるディレクトリの実験は実行しません。
+
+use crate::{
+    affinity::{CpuList, Placement},
+    nice::NiceList,
+    output::Format,
+    policy::PolicyList,
+    scheduler::LoadMode,
+};
+use anyhow::{bail, Context};
+use clap::ArgEnum;
+use serde::Deserialize;
+use std::{
+    fmt, fs,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+/// 実験ファイル全体
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    /// 結果を書き出すディレクトリ
+    #[serde(default = "default_output")]
+    pub output: PathBuf,
+    /// 実験の一覧
+    #[serde(rename = "experiment", default)]
+    pub experiments: Vec<Experiment>,
+}
+
+fn default_output() -> PathBuf {
+    PathBuf::from("results")
+}
+
+/// 実験プログラム
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Program {
+    Sched,
+    SchedNice,
+}
+
+impl Program {
+    /// 実行ファイルの名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Program::Sched => "sched",
+            Program::SchedNice => "sched_nice",
+        }
+    }
+}
+
+/// 値を 1 つだけ書いた条件と配列で書いた条件の両方を受け付ける
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Sweep<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> Default for Sweep<T> {
+    fn default() -> Self {
+        Sweep::Many(Vec::new())
+    }
+}
+
+impl<T: Clone> Sweep<T> {
+    /// 条件の値の一覧。指定がなければ `None` 1 つだけ（実験プログラムの既定値を使う）
+    fn values(&self) -> Vec<Option<T>> {
+        match self {
+            Sweep::One(value) => vec![Some(value.clone())],
+            Sweep::Many(values) if values.is_empty() => vec![None],
+            Sweep::Many(values) => values.iter().cloned().map(Some).collect(),
+        }
+    }
+}
+
+/// 1 つの実験の条件
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    /// 実験の名前（結果のディレクトリ名）
+    pub name: String,
+    /// 実験プログラム（`sched` または `sched_nice`）
+    pub program: Program,
+    /// 同時に動かすプロセス数
+    pub nproc: Sweep<usize>,
+    /// 合計時間（ms）
+    pub total: Sweep<usize>,
+    /// 採取間隔（ms）
+    pub resol: Sweep<usize>,
+    /// 動作させる CPU（`0,2-3` 形式）
+    #[serde(default)]
+    pub cpus: Sweep<String>,
+    /// 子プロセスごとの nice 値（`sched_nice` のみ）
+    #[serde(default)]
+    pub nice: Sweep<String>,
+    /// 子プロセスごとのスケジューリングポリシー
+    #[serde(default)]
+    pub policy: Sweep<String>,
+    /// 子プロセスへの CPU の割り当て方
+    pub placement: Option<String>,
+    /// 負荷のかけ方（`sched` のみ）
+    pub mode: Option<String>,
+    /// 出力形式
+    pub format: Option<String>,
+    /// 記録を図にした SVG も書き出すか
+    #[serde(default)]
+    pub svg: bool,
+    /// 子プロセスごとのスケジューラの統計情報も記録するか
+    #[serde(default)]
+    pub schedstat: bool,
+    /// 子プロセスの代わりにスレッドで負荷をかけるか（`sched` のみ）
+    #[serde(default)]
+    pub threads: bool,
+}
+
+/// 組み合わせを展開した 1 回分の実行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    /// 実験の名前
+    pub experiment: String,
+    /// 条件を表す名前（結果のディレクトリ名）
+    pub name: String,
+    /// 実験プログラム
+    pub program: Program,
+    /// 実験プログラムに渡す引数
+    pub args: Vec<String>,
+    /// 記録のファイル名
+    pub records: String,
+    /// SVG も書き出すか
+    pub svg: bool,
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.experiment, self.name)
+    }
+}
+
+impl Plan {
+    /// 実験ファイルを読み込み、条件を検証します。
+    pub fn load(path: &Path) -> anyhow::Result<Plan> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("{}: read() failed", path.display()))?;
+        let plan: Plan =
+            toml::from_str(&text).with_context(|| format!("{}: invalid plan", path.display()))?;
+        for experiment in &plan.experiments {
+            experiment
+                .check()
+                .with_context(|| format!("{}: experiment {:?}", path.display(), experiment.name))?;
+        }
+        Ok(plan)
+    }
+
+    /// すべての実験の組み合わせを展開します。
+    pub fn runs(&self) -> Vec<Run> {
+        self.experiments.iter().flat_map(Experiment::runs).collect()
+    }
+}
+
+impl Experiment {
+    /// 実験プログラムを動かす前に分かる誤りを検出します。
+    fn check(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() || self.name.contains('/') {
+            bail!("name should be a non-empty directory name");
+        }
+        for (name, sweep) in [
+            ("nproc", &self.nproc),
+            ("total", &self.total),
+            ("resol", &self.resol),
+        ] {
+            let values = sweep.values();
+            if values.iter().any(|v| v.is_none()) {
+                bail!("`{}` is required", name);
+            }
+            if values.contains(&Some(0)) {
+                bail!("`{}` should be >= 1", name);
+            }
+        }
+        for cpus in self.cpus.values().into_iter().flatten() {
+            CpuList::from_str(&cpus).map_err(|e| anyhow::anyhow!("cpus: {}", e))?;
+        }
+        for nice in self.nice.values().into_iter().flatten() {
+            if self.program != Program::SchedNice {
+                bail!("`nice` is only available for sched_nice");
+            }
+            NiceList::from_str(&nice).map_err(|e| anyhow::anyhow!("nice: {}", e))?;
+        }
+        for policy in self.policy.values().into_iter().flatten() {
+            PolicyList::from_str(&policy).map_err(|e| anyhow::anyhow!("policy: {}", e))?;
+        }
+        if let Some(placement) = &self.placement {
+            Placement::from_str(placement, false)
+                .map_err(|e| anyhow::anyhow!("placement: {}", e))?;
+        }
+        if let Some(mode) = &self.mode {
+            if self.program != Program::Sched {
+                bail!("`mode` is only available for sched");
+            }
+            LoadMode::from_str(mode, false).map_err(|e| anyhow::anyhow!("mode: {}", e))?;
+        }
+        if self.threads && self.program != Program::Sched {
+            bail!("`threads` is only available for sched");
+        }
+        if let Some(format) = &self.format {
+            Format::from_str(format, false).map_err(|e| anyhow::anyhow!("format: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// 条件の組み合わせを展開します。
+    fn runs(&self) -> Vec<Run> {
+        let mut runs = Vec::new();
+        for nproc in self.nproc.values().into_iter().flatten() {
+            for cpus in self.cpus.values() {
+                for nice in self.nice.values() {
+                    for policy in self.policy.values() {
+                        for total in self.total.values().into_iter().flatten() {
+                            for resol in self.resol.values().into_iter().flatten() {
+                                runs.push(self.run(
+                                    nproc,
+                                    cpus.as_deref(),
+                                    nice.as_deref(),
+                                    policy.as_deref(),
+                                    total,
+                                    resol,
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        runs
+    }
+
+    fn run(
+        &self,
+        nproc: usize,
+        cpus: Option<&str>,
+        nice: Option<&str>,
+        policy: Option<&str>,
+        total: usize,
+        resol: usize,
+    ) -> Run {
+        let mut name = vec![format!("nproc{}", nproc)];
+        let mut args = match self.program {
+            Program::Sched => vec![nproc.to_string()],
+            Program::SchedNice => vec![format!("--nproc={}", nproc)],
+        };
+        args.push(total.to_string());
+        args.push(resol.to_string());
+        for (option, value) in [("cpus", cpus), ("nice", nice), ("policy", policy)] {
+            if let Some(value) = value {
+                name.push(format!("{}{}", option, dir_name(value)));
+                args.push(format!("--{}={}", option, value));
+            }
+        }
+        name.push(format!("total{}", total));
+        name.push(format!("resol{}", resol));
+        for (option, value) in [
+            ("placement", &self.placement),
+            ("mode", &self.mode),
+            ("format", &self.format),
+        ] {
+            if let Some(value) = value {
+                args.push(format!("--{}={}", option, value));
+            }
+        }
+        if self.schedstat {
+            args.push("--schedstat".to_string());
+        }
+        if self.threads {
+            args.push("--threads".to_string());
+        }
+        let format = self.format.as_deref().unwrap_or("tsv").to_lowercase();
+        Run {
+            experiment: self.name.clone(),
+            name: name.join("-"),
+            program: self.program,
+            args,
+            records: format!("records.{}", format),
+            svg: self.svg,
+        }
+    }
+}
+
+/// 条件の値をディレクトリ名に使える文字列にします。
+fn dir_name(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ',' => '_',
+            ':' | '/' | ' ' => '.',
+            c => c,
+        })
+        .collect()
+}
+
+impl Run {
+    /// 結果を書き出すディレクトリ
+    pub fn dir(&self, output: &Path) -> PathBuf {
+        output.join(&self.experiment).join(&self.name)
+    }
+
+    /// 記録のファイル
+    pub fn records_path(&self, output: &Path) -> PathBuf {
+        self.dir(output).join(&self.records)
+    }
+
+    /// 実行するコマンドライン
+    pub fn command_line(&self, bin_dir: &Path) -> String {
+        let mut line = bin_dir.join(self.program.name()).display().to_string();
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(arg);
+        }
+        line
+    }
+
+    /// 実験プログラムを実行し、結果をディレクトリに書き出します。
+    ///
+    /// 記録は一時ファイルに書き、実験プログラムが成功した場合だけ名前を変えます。
+    /// そのため、途中で失敗した実験は次に実行したときにやり直されます。
+    pub fn execute(&self, bin_dir: &Path, output: &Path) -> anyhow::Result<()> {
+        let dir = self.dir(output);
+        fs::create_dir_all(&dir).with_context(|| format!("{}: mkdir failed", dir.display()))?;
+
+        let records = self.records_path(output);
+        let partial = dir.join(format!("{}.partial", self.records));
+        let stderr = dir.join("stderr.txt");
+        fs::write(dir.join("command.txt"), self.command_line(bin_dir) + "\n")
+            .with_context(|| format!("{}: write() failed", dir.display()))?;
+
+        let mut command = Command::new(bin_dir.join(self.program.name()));
+        command.args(&self.args);
+        if self.svg {
+            command.arg(format!("--svg={}", dir.join("plot.svg").display()));
+        }
+        let status = command
+            .stdout(File::create(&partial).with_context(|| format!("{}", partial.display()))?)
+            .stderr(File::create(&stderr).with_context(|| format!("{}", stderr.display()))?)
+            .status()
+            .with_context(|| format!("failed to execute {}", self.command_line(bin_dir)))?;
+        if !status.success() {
+            let _ = fs::remove_file(&partial);
+            bail!(
+                "{} ({}, see {})",
+                self.command_line(bin_dir),
+                status,
+                stderr.display()
+            );
+        }
+        fs::rename(&partial, &records)
+            .with_context(|| format!("{}: rename() failed", records.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(text: &str) -> Plan {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn runs_expand_every_combination() {
+        let plan = plan(
+            r#"
+            [[experiment]]
+            name = "2core"
+            program = "sched"
+            nproc = [1, 2]
+            cpus = ["0,1", "0,4"]
+            total = 100
+            resol = 1
+            schedstat = true
+            "#,
+        );
+        let runs = plan.runs();
+        let names: Vec<&str> = runs.iter().map(|run| run.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "nproc1-cpus0_1-total100-resol1",
+                "nproc1-cpus0_4-total100-resol1",
+                "nproc2-cpus0_1-total100-resol1",
+                "nproc2-cpus0_4-total100-resol1",
+            ]
+        );
+        assert_eq!(runs[3].args, ["2", "100", "1", "--cpus=0,4", "--schedstat"]);
+        assert_eq!(runs[3].to_string(), "2core/nproc2-cpus0_4-total100-resol1");
+        assert_eq!(runs[3].records, "records.tsv");
+    }
+
+    #[test]
+    fn runs_pass_nproc_as_option_to_sched_nice() {
+        let plan = plan(
+            r#"
+            [[experiment]]
+            name = "nice"
+            program = "sched_nice"
+            nproc = 3
+            nice = ["0,5,10", "19"]
+            total = 100
+            resol = 1
+            format = "jsonl"
+            "#,
+        );
+        let runs = plan.runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(
+            runs[0].args,
+            ["--nproc=3", "100", "1", "--nice=0,5,10", "--format=jsonl"]
+        );
+        assert_eq!(runs[0].name, "nproc3-nice0_5_10-total100-resol1");
+        assert_eq!(runs[1].name, "nproc3-nice19-total100-resol1");
+        assert_eq!(runs[0].records, "records.jsonl");
+        assert_eq!(plan.output, PathBuf::from("results"));
+    }
+
+    #[test]
+    fn check_rejects_invalid_experiments() {
+        let base = "name = \"x\"\nnproc = 2\ntotal = 100\nresol = 1\n";
+        for extra in [
+            "program = \"sched\"\nnice = \"5\"",
+            "program = \"sched_nice\"\nmode = \"cputime\"",
+            "program = \"sched_nice\"\nthreads = true",
+            "program = \"sched\"\ncpus = \"a\"",
+            "program = \"sched\"\nformat = \"xml\"",
+        ] {
+            let plan = plan(&format!("[[experiment]]\n{}{}\n", base, extra));
+            assert!(plan.experiments[0].check().is_err(), "{}", extra);
+        }
+        let plan = plan("[[experiment]]\nname = \"x\"\nprogram = \"sched\"\nnproc = [1, 0]\ntotal = 100\nresol = 1\n");
+        assert!(plan.experiments[0].check().is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! 実験結果に添える、実験した環境の情報
+//!
+//! 読み出せなかった項目（仮想マシンで `cpufreq` がない場合など）は `None` にします。
+//!
+//! - `/proc/cpuinfo`: CPU のモデル名
+//! - `/sys/devices/system/cpu`: オンラインの CPU、物理コア、CPU 0 のキャッシュ、CPU 0 の周波数ガバナ
+//! - `/proc/meminfo`: メモリの容量
+//! - `/sys/kernel/mm/transparent_hugepage/enabled`: THP の設定
+
+use crate::{affinity, output::Format};
+use nix::sys::utsname::uname;
+use serde::Serialize;
+use std::{collections::BTreeSet, fs, io};
+
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// 実験した環境
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// カーネルのバージョン
+    pub kernel: String,
+    /// CPU のモデル名
+    pub cpu_model: Option<String>,
+    /// オンラインの論理 CPU の数
+    pub cpus_online: Option<usize>,
+    /// オンラインの CPU の物理コアの数
+    pub cores: Option<usize>,
+    /// CPU 0 のキャッシュ（`L1d:32K,L1i:32K,L2:1024K` 形式）
+    pub caches: Option<String>,
+    /// メモリの容量（KB）
+    pub memory_kb: Option<u64>,
+    /// CPU 0 の周波数ガバナ
+    pub governor: Option<String>,
+    /// THP の設定（`always`, `madvise` または `never`）
+    pub thp: Option<String>,
+    /// 実験プログラムをビルドしたソースの git リビジョン（コミットしていない変更があれば `-dirty` を付ける）
+    pub git_rev: Option<String>,
+}
+
+impl Fingerprint {
+    /// 実行中の環境から情報を集めます。
+    pub fn collect() -> Fingerprint {
+        let online = read("/sys/devices/system/cpu/online")
+            .and_then(|list| affinity::parse_cpu_list(&list).ok());
+        Fingerprint {
+            kernel: uname().release().to_string(),
+            cpu_model: cpu_model(),
+            cpus_online: online.as_ref().map(Vec::len),
+            cores: online.as_deref().and_then(cores),
+            caches: caches(),
+            memory_kb: memory_kb(),
+            governor: read(&format!("{}/cpu0/cpufreq/scaling_governor", CPU_DIR)),
+            thp: read("/sys/kernel/mm/transparent_hugepage/enabled").and_then(|s| selected(&s)),
+            git_rev: git_rev(),
+        }
+    }
+
+    /// 実験条件と同じ `# key: value` 形式のコメント行として標準出力に書き出します。
+    ///
+    /// 独自の形式で結果を出力する実験プログラムが、結果の前に出力するために使います。
+    pub fn print(&self) -> io::Result<()> {
+        Format::Tsv.write_header(&mut io::stdout(), self)
+    }
+}
+
+/// ファイルの内容を前後の空白を除いて読み出します。
+fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "model name").then(|| value.trim().to_string())
+    })
+}
+
+/// `(パッケージ, コア)` の組の数を物理コアの数とします。
+fn cores(online: &[usize]) -> Option<usize> {
+    let mut cores = BTreeSet::new();
+    for cpu in online {
+        let topology = |name: &str| read(&format!("{}/cpu{}/topology/{}", CPU_DIR, cpu, name));
+        cores.insert((topology("physical_package_id")?, topology("core_id")?));
+    }
+    Some(cores.len())
+}
+
+fn caches() -> Option<String> {
+    let dir = format!("{}/cpu0/cache", CPU_DIR);
+    let mut indexes: Vec<String> = fs::read_dir(&dir)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("index"))
+        .collect();
+    indexes.sort();
+
+    let caches: Vec<String> = indexes
+        .iter()
+        .filter_map(|index| {
+            let attr = |name: &str| read(&format!("{}/{}/{}", dir, index, name));
+            let suffix = match attr("type")?.as_str() {
+                "Data" => "d",
+                "Instruction" => "i",
+                _ => "",
+            };
+            Some(format!("L{}{}:{}", attr("level")?, suffix, attr("size")?))
+        })
+        .collect();
+    (!caches.is_empty()).then(|| caches.join(","))
+}
+
+fn memory_kb() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix("MemTotal:")?;
+        value.trim().trim_end_matches("kB").trim().parse().ok()
+    })
+}
+
+/// `always [madvise] never` のような設定から、選ばれている `[]` 内の値を取り出します。
+fn selected(choices: &str) -> Option<String> {
+    let start = choices.find('[')? + 1;
+    let end = start + choices[start..].find(']')?;
+    Some(choices[start..end].to_string())
+}
+
+/// ビルド時に `build.rs` が埋め込んだリビジョンを返します。
+fn git_rev() -> Option<String> {
+    option_env!("PLAYGROUND_GIT_REV").map(str::to_string)
+}
@@ -0,0 +1,320 @@
+//! 実行可能になったプロセスが実際に動き出すまでの待ち時間（起床レイテンシ）の計測
+//!
+//! 2 つの子プロセスが 2 本のパイプで交互に時刻を送り合います。
+//! 受け取った側は、読み出しから戻った時刻と相手が書き込んだ時刻の差を待ち時間として記録します。
+//!
+//! ```text
+//! 子プロセス 0 --(パイプ A: 送った時刻)--> 子プロセス 1
+//! 子プロセス 0 <--(パイプ B: 送った時刻)-- 子プロセス 1
+//! ```
+//!
+//! 2 つを同じ CPU に固定すると、書き込んだ側が読み出しで眠ってから相手に切り替わるまでの時間になり、
+//! 別々の CPU に固定すると、別の CPU で眠っているプロセスを起こすまでの時間になります。
+
+use crate::{
+    clock::{get_time, NSECS_PER_SEC},
+    scheduler::{self, Child},
+    stats::percentile,
+};
+use anyhow::{anyhow, bail, Context};
+use nix::{
+    libc::{EXIT_FAILURE, EXIT_SUCCESS},
+    unistd::{close, pipe},
+};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    os::unix::io::{FromRawFd, RawFd},
+};
+
+/// 交互に起こし合う子プロセスの数
+pub const NPROC: usize = 2;
+
+/// 1 回分の待ち時間
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// 往復の番号（ウォームアップを除く）
+    pub round: usize,
+    /// 時刻を送った子プロセス
+    pub from: usize,
+    /// 起こされた子プロセス
+    pub to: usize,
+    /// 書き込みから読み出した側が動き出すまでの時間（ns）
+    pub latency_ns: u64,
+}
+
+/// 待ち時間の分布
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// 向き（`0->1`, `1->0` または両方向をまとめた `all`）
+    pub direction: String,
+    /// 計測回数
+    pub count: usize,
+    /// 最小値（ns）
+    pub min_ns: u64,
+    /// 平均値（ns）
+    pub mean_ns: u64,
+    /// 中央値（ns）
+    pub p50_ns: u64,
+    /// 90 パーセンタイル（ns）
+    pub p90_ns: u64,
+    /// 99 パーセンタイル（ns）
+    pub p99_ns: u64,
+    /// 最大値（ns）
+    pub max_ns: u64,
+}
+
+impl Summary {
+    fn new(direction: String, latencies: &[u64]) -> Summary {
+        let mut sorted = latencies.to_vec();
+        sorted.sort_unstable();
+        Summary {
+            direction,
+            count: sorted.len(),
+            min_ns: sorted[0],
+            mean_ns: sorted.iter().sum::<u64>() / sorted.len() as u64,
+            p50_ns: percentile(&sorted, 50.0),
+            p90_ns: percentile(&sorted, 90.0),
+            p99_ns: percentile(&sorted, 99.0),
+            max_ns: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+/// 向きごとと両方向をまとめた待ち時間の分布を返します。
+pub fn summarize(samples: &[Sample]) -> Vec<Summary> {
+    let mut summaries = Vec::new();
+    for from in 0..NPROC {
+        let latencies: Vec<u64> = samples
+            .iter()
+            .filter(|s| s.from == from)
+            .map(|s| s.latency_ns)
+            .collect();
+        if !latencies.is_empty() {
+            let to = (from + 1) % NPROC;
+            summaries.push(Summary::new(format!("{}->{}", from, to), &latencies));
+        }
+    }
+    let all: Vec<u64> = samples.iter().map(|s| s.latency_ns).collect();
+    if !all.is_empty() {
+        summaries.push(Summary::new("all".to_string(), &all));
+    }
+    summaries
+}
+
+fn now_nsec() -> u64 {
+    let now = get_time();
+    now.tv_sec() as u64 * NSECS_PER_SEC as u64 + now.tv_nsec() as u64
+}
+
+fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_FAILURE);
+}
+
+/// 子プロセスの処理
+///
+/// 子プロセス 0 が先に時刻を送り、以降は受け取ったら送り返すことを繰り返します。
+/// 受け取るたびに計測した待ち時間を、最後にまとめて `out` に 1 行ずつ書き出し終了します。
+fn child_fn(id: usize, rounds: usize, warmup: usize, rx: RawFd, tx: RawFd, out: File) -> ! {
+    let mut rx = unsafe { File::from_raw_fd(rx) };
+    let mut tx = unsafe { File::from_raw_fd(tx) };
+    let mut latencies = Vec::with_capacity(rounds);
+    let mut buf = [0u8; 8];
+
+    for round in 0..warmup + rounds {
+        if id == 0 {
+            if let Err(e) = tx.write_all(&now_nsec().to_ne_bytes()) {
+                exit_with(format!("child {}: write() failed: {}", id, e));
+            }
+        }
+        if let Err(e) = rx.read_exact(&mut buf) {
+            exit_with(format!("child {}: read() failed: {}", id, e));
+        }
+        let latency = now_nsec().saturating_sub(u64::from_ne_bytes(buf));
+        if round >= warmup {
+            latencies.push(latency);
+        }
+        if id != 0 {
+            if let Err(e) = tx.write_all(&now_nsec().to_ne_bytes()) {
+                exit_with(format!("child {}: write() failed: {}", id, e));
+            }
+        }
+    }
+
+    let mut out = BufWriter::new(out);
+    for latency in latencies {
+        if let Err(e) = writeln!(out, "{}", latency) {
+            exit_with(format!("write() failed: {}", e));
+        }
+    }
+    if let Err(e) = out.flush() {
+        exit_with(format!("write() failed: {}", e));
+    }
+    std::process::exit(EXIT_SUCCESS);
+}
+
+/// 子プロセスから送られた待ち時間をパイプが閉じられるまで読み出します。
+fn read_latencies(child: &Child) -> anyhow::Result<Vec<u64>> {
+    let mut latencies = Vec::new();
+    for line in BufReader::new(&child.input).lines() {
+        let line = line.context("read() failed")?;
+        match line.parse() {
+            Ok(latency) => latencies.push(latency),
+            Err(_) => bail!("malformed record: {:?}", line),
+        }
+    }
+    Ok(latencies)
+}
+
+/// 2 つの子プロセスに `warmup + rounds` 回往復させ、ウォームアップ後の待ち時間を返します。
+///
+/// 各子プロセスは、往復を始める前に `setup` をプロセス番号を引数にして呼び出し、失敗した場合は異常終了します。
+/// どちらかの子プロセスが異常終了した場合は、相手もパイプが閉じられて終了し、エラーを返します。
+pub fn run<F>(rounds: usize, warmup: usize, setup: F) -> anyhow::Result<Vec<Sample>>
+where
+    F: Fn(usize) -> Result<(), String>,
+{
+    let (a_read, a_write) = pipe().context("pipe() failed")?;
+    let (b_read, b_write) = match pipe() {
+        Ok(fds) => fds,
+        Err(e) => {
+            let _ = close(a_read);
+            let _ = close(a_write);
+            bail!("pipe() failed: {}", e);
+        }
+    };
+
+    let children = scheduler::spawn(NPROC, |id, out| {
+        // 使わない側を閉じておくと、相手が終了したときに read() が EOF を返す
+        let (rx, tx, unused) = match id {
+            0 => (b_read, a_write, [a_read, b_write]),
+            _ => (a_read, b_write, [b_read, a_write]),
+        };
+        for fd in unused {
+            let _ = close(fd);
+        }
+        if let Err(e) = setup(id) {
+            exit_with(format!("child {}: {}", id, e));
+        }
+        child_fn(id, rounds, warmup, rx, tx, out)
+    });
+    for fd in [a_read, a_write, b_read, b_write] {
+        let _ = close(fd);
+    }
+    let children = children?;
+
+    let mut samples = Vec::with_capacity(NPROC * rounds);
+    let mut errors = Vec::new();
+    for child in &children {
+        let delivered = read_latencies(child);
+        if let Err(e) = child.reap() {
+            errors.push(e);
+            continue;
+        }
+        match delivered {
+            Ok(latencies) if latencies.len() == rounds => {
+                samples.extend(
+                    latencies
+                        .into_iter()
+                        .enumerate()
+                        .map(|(round, latency_ns)| Sample {
+                            round,
+                            from: (child.id + 1) % NPROC,
+                            to: child.id,
+                            latency_ns,
+                        }),
+                );
+            }
+            Ok(latencies) => errors.push(format!(
+                "child {} (pid {}) exited before delivering its records ({} of {})",
+                child.id,
+                child.pid,
+                latencies.len(),
+                rounds
+            )),
+            Err(e) => errors.push(format!("child {} (pid {}): {:#}", child.id, child.pid, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!(errors.join("\n")));
+    }
+
+    // 各往復の中では 0->1 が先に起きる
+    samples.sort_by_key(|s| (s.round, s.from));
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(from: usize, latencies: impl IntoIterator<Item = u64>) -> Vec<Sample> {
+        latencies
+            .into_iter()
+            .enumerate()
+            .map(|(round, latency_ns)| Sample {
+                round,
+                from,
+                to: (from + 1) % NPROC,
+                latency_ns,
+            })
+            .collect()
+    }
+
+    fn summary(direction: &str, count: usize, stats: [u64; 6]) -> Summary {
+        let [min_ns, mean_ns, p50_ns, p90_ns, p99_ns, max_ns] = stats;
+        Summary {
+            direction: direction.to_string(),
+            count,
+            min_ns,
+            mean_ns,
+            p50_ns,
+            p90_ns,
+            p99_ns,
+            max_ns,
+        }
+    }
+
+    #[test]
+    fn summary_sorts_before_taking_percentiles() {
+        let latencies: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(
+            Summary::new("0->1".to_string(), &latencies),
+            summary("0->1", 100, [1, 50, 50, 90, 99, 100])
+        );
+        assert_eq!(
+            Summary::new("0->1".to_string(), &[7]),
+            summary("0->1", 1, [7, 7, 7, 7, 7, 7])
+        );
+    }
+
+    #[test]
+    fn summarize_gives_each_direction_and_both() {
+        let mut all = samples(0, (1..=100).rev());
+        all.extend(samples(1, [5, 5, 5]));
+        assert_eq!(
+            summarize(&all),
+            [
+                summary("0->1", 100, [1, 50, 50, 90, 99, 100]),
+                summary("1->0", 3, [5, 5, 5, 5, 5, 5]),
+                // 1..=100 に 5 が 3 つ加わるので、52 番目（中央値）は 49、93 番目（90 パーセンタイル）は 90
+                summary("all", 103, [1, 49, 49, 90, 99, 100]),
+            ]
+        );
+    }
+
+    #[test]
+    fn summarize_skips_directions_without_samples() {
+        assert!(summarize(&[]).is_empty());
+        assert_eq!(
+            summarize(&samples(1, [10, 30])),
+            [
+                summary("1->0", 2, [10, 20, 10, 30, 30, 30]),
+                summary("all", 2, [10, 20, 10, 30, 30, 30]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! 各実験プログラムから共通で使う処理をまとめたライブラリ
+//!
+//! - [`clock`] - 時刻の取得と差分の計算
+//! - [`load`] - CPU時間を一定量消費する負荷処理とその推定
+//! - [`args`] - コマンドライン引数の検証と共通の引数
+//! - [`scheduler`] - スケジューラの実験で子プロセスが実行する処理
+//! - [`schedstat`] - カーネルが記録している子プロセスごとのスケジューラの統計情報
+//! - [`latency`] - プロセスが起こされてから動き出すまでの待ち時間の計測
+//! - [`stats`] - 計測値の要約統計量
+//! - [`timeslice`] - 記録から求めたタイムスライスと待ち時間の分布
+//! - [`output`] - スケジューラの実験結果の出力形式
+//! - [`plot`] - スケジューラの実験結果の SVG による図示
+//! - [`affinity`] - CPU の割り当ての取得と設定
+//! - [`policy`] - スケジューリングポリシーの設定
+//! - [`nice`] - nice 値の設定と取得
+//! - [`cfs`] - CFS による CPU 時間の取り分の予測と実測値との比較
+//! - [`cgroup`] - cgroup v2 の CPU コントローラを使った実験のためのグループの作成と後片付け
+//! - [`storage`] - ストレージデバイスへの I/O の性能の計測
+//! - [`testfile`] - I/O の実験のためのテストファイルとループデバイス
+//! - [`aligned`] - `O_DIRECT` で使う、アドレスを揃えたバッファとアラインメントの要件
+//! - [`experiment`] - 実験ファイルに書いた条件の組み合わせの実行
+//! - [`fingerprint`] - 実験結果に添える、実験した環境の情報
+//! - [`cli`] - 各実験プログラムとサンプルのコマンドライン引数と処理
+
+pub mod affinity;
+pub mod aligned;
+pub mod args;
+pub mod cfs;
+pub mod cgroup;
+pub mod cli;
+pub mod clock;
+pub mod experiment;
+pub mod fingerprint;
+pub mod latency;
+pub mod load;
+pub mod nice;
+pub mod output;
+pub mod plot;
+pub mod policy;
+pub mod schedstat;
+pub mod scheduler;
+pub mod stats;
+pub mod storage;
+pub mod testfile;
+pub mod timeslice;
@@ -0,0 +1,62 @@
+//! 各実験プログラムをサブコマンドとしてまとめたプログラム
+//!
+//! 各サブコマンドは、[`playground::cli`] の同じ処理を呼び出す単独の実験プログラム（`sched` など）や
+//! サンプル（`examples/fork.rs` など）と同じ引数を受け付け、同じように動作します。
+//!
+//! # 終了コード
+//! - `0`: 成功
+//! - `1`: 実行中のエラー（システムコールの失敗など）
+//! - `2`: 引数の誤り（組み合わせの誤りや、この権限では設定できない `--policy` と `--nice` を含む）
+
+use clap::{Parser, Subcommand};
+use playground::cli::{
+    busy_loop, cache, cow, experiment, filemap, fork, fork_and_exec, io, mmap, plot, ppidloop,
+    sched, sched_cgroup, sched_nice, segv,
+};
+
+/// 「Linux のしくみ」の実験プログラム
+#[derive(Parser, Debug)]
+#[clap(name = "linux-in-practice")]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    Sched(sched::Args),
+    Nice(sched_nice::Args),
+    Cgroup(sched_cgroup::Args),
+    Cache(cache::Args),
+    Cow(cow::Args),
+    Filemap(filemap::Args),
+    Io(io::Args),
+    Plot(plot::Args),
+    Experiment(experiment::Args),
+    Fork(fork::Args),
+    Exec(fork_and_exec::Args),
+    Segv(segv::Args),
+    Mmap(mmap::Args),
+    Ppidloop(ppidloop::Args),
+    Loop(busy_loop::Args),
+}
+
+fn main() {
+    match Cli::parse().command {
+        Command::Sched(args) => sched::run(args),
+        Command::Nice(args) => sched_nice::run(args),
+        Command::Cgroup(args) => sched_cgroup::run(args),
+        Command::Cache(args) => cache::run(args),
+        Command::Cow(args) => cow::run(args),
+        Command::Filemap(args) => filemap::run(args),
+        Command::Io(args) => io::run(args),
+        Command::Plot(args) => plot::run(args),
+        Command::Experiment(args) => experiment::run(args),
+        Command::Fork(args) => fork::run(args),
+        Command::Exec(args) => fork_and_exec::run(args),
+        Command::Segv(args) => segv::run(args),
+        Command::Mmap(args) => mmap::run(args),
+        Command::Ppidloop(args) => ppidloop::run(args),
+        Command::Loop(args) => busy_loop::run(args),
+    }
+}
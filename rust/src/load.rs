@@ -0,0 +1,120 @@
+//! CPU時間を一定量消費する負荷処理
+
+use crate::clock::{diff_nsec, get_cputime, get_time, NSECS_PER_MSEC};
+use std::{fmt, hint::black_box};
+
+/// 推定に使う1回あたりの計測時間の下限（ns）
+const NSECS_PER_SAMPLE: usize = 50 * NSECS_PER_MSEC;
+/// [`load_cputime`] で CPU 時間を確認する間隔（ループ回数）
+const NLOOP_PER_CPUTIME_CHECK: usize = 10_000;
+/// 推定時に計測する回数
+pub const NSAMPLE_FOR_ESTIMATION: usize = 5;
+
+/// `nloop` 回のループで CPU 時間を消費します。
+///
+/// 空ループはリリースビルドで最適化により取り除かれてしまうため、
+/// ループ変数を [`black_box`] に渡してコンパイラから処理内容を隠します。
+/// 推定時と実行時で同じ機械語が動くよう、インライン展開はさせません。
+#[inline(never)]
+pub fn load(nloop: usize) {
+    for i in 0..nloop {
+        black_box(i);
+    }
+}
+
+/// 呼び出したスレッドの CPU 時間が `nsec` 進むまで CPU 時間を消費します。
+///
+/// ループ回数の推定を使わないため、CPU の動作周波数が変わったり
+/// 他のプロセスと CPU を取り合ったりしても、消費する CPU 時間は変わりません。
+pub fn load_cputime(nsec: usize) {
+    let before = get_cputime();
+    while diff_nsec(before, get_cputime()) < nsec {
+        load(NLOOP_PER_CPUTIME_CHECK);
+    }
+}
+
+/// `nloop` 回の [`load`] にかかった時間（ns）を返します。
+fn measure(nloop: usize) -> usize {
+    let before = get_time();
+    load(nloop);
+    let after = get_time();
+    diff_nsec(before, after).max(1)
+}
+
+/// [`calibrate`] による推定結果
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    /// 計測ごとの 1ms あたりのループ回数
+    samples: Vec<usize>,
+}
+
+impl Calibration {
+    /// 1ms あたりのループ回数（各計測の中央値）
+    pub fn loops_per_msec(&self) -> usize {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        sorted[sorted.len() / 2]
+    }
+
+    /// 計測ごとの 1ms あたりのループ回数
+    pub fn samples(&self) -> &[usize] {
+        &self.samples
+    }
+
+    /// 計測値の平均
+    pub fn mean(&self) -> f64 {
+        self.samples.iter().sum::<usize>() as f64 / self.samples.len() as f64
+    }
+
+    /// 計測値の標準偏差
+    pub fn stddev(&self) -> f64 {
+        let mean = self.mean();
+        let var = self
+            .samples
+            .iter()
+            .map(|&s| (s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.samples.len() as f64;
+        var.sqrt()
+    }
+
+    /// 推定の信頼度の目安として、変動係数（標準偏差 / 平均）を返します。
+    /// 値が小さいほど計測ごとのばらつきが少ないことを表します。
+    pub fn relative_stddev(&self) -> f64 {
+        self.stddev() / self.mean()
+    }
+}
+
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} loops/ms (stddev {:.1}%, {} samples)",
+            self.loops_per_msec(),
+            self.relative_stddev() * 100.0,
+            self.samples.len()
+        )
+    }
+}
+
+/// CPU時間を 1ms 使う処理に必要なループ回数を `nsample` 回計測して推定します。
+///
+/// 1回の計測が [`NSECS_PER_SAMPLE`] 以上になるまでループ回数を倍々に増やしてから、
+/// 同じループ回数で `nsample` 回計測します。
+pub fn calibrate(nsample: usize) -> Calibration {
+    let mut nloop = 1024;
+    while measure(nloop) < NSECS_PER_SAMPLE {
+        nloop *= 2;
+    }
+
+    // ループ回数をかかった時間でわり、1nsあたりのループ回数を計算し、NSECS_PER_MSECを掛け、単位をmsにする
+    let samples = (0..nsample.max(1))
+        .map(|_| nloop * NSECS_PER_MSEC / measure(nloop))
+        .collect();
+    Calibration { samples }
+}
+
+/// CPU時間を 1ms 使う処理に必要なループ回数を推定します。
+pub fn loops_per_msec() -> usize {
+    calibrate(NSAMPLE_FOR_ESTIMATION).loops_per_msec()
+}
@@ -0,0 +1,168 @@
+//! nice 値の設定と取得
+//!
+//! `--nice 0,5,10,19` のように、子プロセスごとの nice 値を先頭から順に指定します。
+//! 一覧より後ろの子プロセスは親プロセスの nice 値のまま動きます。
+//! nice 値を現在より小さくするには、`CAP_SYS_NICE` があるか、`RLIMIT_NICE` が `20 - nice` 以上である必要があります。
+
+use crate::policy::has_cap_sys_nice;
+use nix::{errno::Errno, libc};
+use std::{fmt, str::FromStr};
+
+/// 指定できる nice 値の範囲
+pub const NICE_RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+
+/// 呼び出したプロセスの nice 値を返します。
+pub fn get_nice() -> nix::Result<i32> {
+    // getpriority() は -1 を正常な値として返すことがあるため、errno で失敗を判定する
+    Errno::clear();
+    let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    match Errno::last() {
+        Errno::UnknownErrno => Ok(nice),
+        e => Err(e),
+    }
+}
+
+/// 呼び出したプロセスの nice 値を `nice` に変更します。
+pub fn set_nice(nice: i32) -> nix::Result<()> {
+    Errno::result(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) }).map(drop)
+}
+
+/// 子プロセスごとの nice 値の一覧
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NiceList(pub Vec<i32>);
+
+impl NiceList {
+    /// `id` 番目の子プロセスの nice 値を返します。
+    pub fn for_child(&self, id: usize) -> Option<i32> {
+        self.0.get(id).copied()
+    }
+
+    /// 一覧の長さが `nproc` 以下であり、
+    /// このプロセスの権限ですべての nice 値を設定できるかを確かめます。
+    pub fn check(&self, nproc: usize) -> Result<(), String> {
+        if self.0.len() > nproc {
+            return Err(format!(
+                "--nice has {} values but nproc is {}: {}",
+                self.0.len(),
+                nproc,
+                self
+            ));
+        }
+        let current = get_nice().map_err(|e| format!("getpriority() failed: {}", e))?;
+        let limit = nice_limit();
+        if let Some(&nice) = self
+            .0
+            .iter()
+            .find(|&&nice| nice < current && !within_limit(nice, limit))
+        {
+            if !has_cap_sys_nice() {
+                return Err(format!(
+                    "lowering nice value from {} to {} requires CAP_SYS_NICE or RLIMIT_NICE >= {} (current: {}); run as root or raise the limit with `ulimit -e`",
+                    current,
+                    nice,
+                    20 - nice,
+                    limit
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `RLIMIT_NICE` のソフトリミットを返します。
+fn nice_limit() -> u64 {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    match unsafe { libc::getrlimit(libc::RLIMIT_NICE, &mut rlim) } {
+        0 => rlim.rlim_cur,
+        _ => 0,
+    }
+}
+
+/// `RLIMIT_NICE` が `limit` のとき、権限がなくても nice 値を `nice` まで下げられるかを返します。
+///
+/// カーネルはリミットを `20 - nice` の形で比べます（`RLIM_INFINITY` ならどこまでも下げられる）。
+fn within_limit(nice: i32, limit: u64) -> bool {
+    (20 - nice) as u64 <= limit
+}
+
+impl FromStr for NiceList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut list = Vec::new();
+        for value in s.split(',') {
+            let nice = value
+                .trim()
+                .parse::<i32>()
+                .map_err(|e| format!("invalid nice value {:?}: {}", value, e))?;
+            if !NICE_RANGE.contains(&nice) {
+                return Err(format!(
+                    "nice value should be {}..={}: {}",
+                    NICE_RANGE.start(),
+                    NICE_RANGE.end(),
+                    nice
+                ));
+            }
+            list.push(nice);
+        }
+        Ok(NiceList(list))
+    }
+}
+
+impl fmt::Display for NiceList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let values: Vec<String> = self.0.iter().map(i32::to_string).collect();
+        f.write_str(&values.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nice_list_parses_values_in_order() {
+        assert_eq!("0,5,-3".parse(), Ok(NiceList(vec![0, 5, -3])));
+        assert_eq!(" 19 ".parse(), Ok(NiceList(vec![19])));
+        assert_eq!("-20,19".parse(), Ok(NiceList(vec![-20, 19])));
+    }
+
+    #[test]
+    fn nice_list_rejects_invalid_values() {
+        for s in ["", "x", "1,,2", "-21", "20", "0,5,100", "1.5"] {
+            assert!(s.parse::<NiceList>().is_err(), "{:?} should be rejected", s);
+        }
+    }
+
+    #[test]
+    fn nice_list_round_trips_and_gives_values_per_child() {
+        let list: NiceList = "-5, 0,10".parse().unwrap();
+        assert_eq!(list.to_string(), "-5,0,10");
+        assert_eq!(list.for_child(0), Some(-5));
+        assert_eq!(list.for_child(2), Some(10));
+        assert_eq!(list.for_child(3), None);
+    }
+
+    #[test]
+    fn check_rejects_more_values_than_children() {
+        let list = NiceList(vec![19, 19, 19]);
+        assert!(list.check(2).is_err());
+        // nice 値を上げるだけなら権限は要らない
+        assert_eq!(list.check(3), Ok(()));
+        assert_eq!(list.check(4), Ok(()));
+    }
+
+    #[test]
+    fn within_limit_follows_rlimit_nice() {
+        // 既定のリミット 0 では下げられない
+        assert!(!within_limit(19, 0));
+        // リミット 20 なら 0 まで、40 なら -20 まで下げられる
+        assert!(within_limit(0, 20));
+        assert!(!within_limit(-1, 20));
+        assert!(within_limit(-20, 40));
+        assert!(within_limit(-20, libc::RLIM_INFINITY));
+    }
+}
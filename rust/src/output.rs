@@ -0,0 +1,420 @@
+//! スケジューラの実験結果の出力形式
+//!
+//! どの形式でも、最初に実験条件と実験した環境を表すメタデータを、続いて記録を 1 行ずつ出力します。
+//!
+//! - `tsv`: メタデータは `#` から始まるコメント行。記録は従来どおりタブ区切り
+//! - `csv`: メタデータは `#` から始まるコメント行。続けて列名の行と、カンマ区切りの記録
+//!   （カンマ、引用符、改行を含む値は RFC 4180 のとおり引用符で囲む）
+//! - `jsonl`: 1 行目が `"type": "metadata"` のオブジェクト、以降が `"type": "record"` のオブジェクト
+//!
+//! 記録の後に、子プロセスごとの統計情報などを付け加えることもあります（[`Format::write_footer`]）。
+
+use crate::{
+    affinity::{self, Placement},
+    cgroup::{self, GroupSpec},
+    fingerprint::Fingerprint,
+    latency,
+    load::Calibration,
+    nice::NiceList,
+    policy::PolicyList,
+    scheduler::LoadMode,
+};
+use clap::ArgEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+/// 出力形式
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// タブ区切り（列名なし）
+    Tsv,
+    /// カンマ区切り（列名あり）
+    Csv,
+    /// 1 行に 1 つの JSON オブジェクト
+    Jsonl,
+}
+
+/// 実験条件
+#[derive(Serialize, Debug, Clone)]
+pub struct Metadata {
+    /// 実験プログラムの名前
+    pub program: &'static str,
+    /// 同時に動かしたプロセス数
+    pub nproc: usize,
+    /// 合計時間（ms、`latency` のときは `None`）
+    pub total: Option<usize>,
+    /// 採取間隔（ms、`latency` のときは `None`）
+    pub resol: Option<usize>,
+    /// 負荷のかけ方（`loops`, `cputime`、または起床レイテンシを計る `latency`）
+    pub mode: &'static str,
+    /// 記録した往復の回数（`latency` のときのみ）
+    pub rounds: Option<usize>,
+    /// 記録する前に捨てた往復の回数（`latency` のときのみ）
+    pub latency_warmup: Option<usize>,
+    /// 負荷をかけたのが子プロセス（`processes`）かスレッド（`threads`）か
+    pub workers: &'static str,
+    /// 1ms あたりのループ回数（`loops` のときのみ）
+    pub loops_per_msec: Option<usize>,
+    /// ループ回数の推定値の変動係数（`loops` のときのみ）
+    pub calibration_stddev: Option<f64>,
+    /// 動作可能な CPU（`0,2-3` 形式）
+    pub cpus: String,
+    /// 子プロセスへの CPU の割り当て方
+    pub placement: &'static str,
+    /// 子プロセスごとのスケジューリングポリシー（`0:fifo:10,1:other:0` 形式）
+    pub policies: Option<String>,
+    /// 子プロセスごとに指定した nice 値（`0,5,10` 形式）
+    pub nice: Option<String>,
+    /// 子プロセスを入れた cgroup（`g0:nproc=1,weight=100;g1:...` 形式）
+    pub cgroups: Option<String>,
+    /// 実験した環境
+    #[serde(flatten)]
+    pub environment: Fingerprint,
+}
+
+impl Metadata {
+    /// 実行中の環境から CPU の割り当てと実験環境（[`Fingerprint`]）の情報を集めてメタデータを作ります。
+    pub fn new(
+        program: &'static str,
+        nproc: usize,
+        total: usize,
+        resol: usize,
+        mode: LoadMode,
+    ) -> Metadata {
+        let mode = match mode {
+            LoadMode::Loops => "loops",
+            LoadMode::Cputime => "cputime",
+        };
+        Metadata {
+            total: Some(total),
+            resol: Some(resol),
+            ..Metadata::base(program, nproc, mode)
+        }
+    }
+
+    /// 2 つのプロセスに `rounds` 回往復させて起床レイテンシを計る実験のメタデータを作ります。
+    pub fn latency(program: &'static str, rounds: usize, warmup: usize) -> Metadata {
+        Metadata {
+            rounds: Some(rounds),
+            latency_warmup: Some(warmup),
+            ..Metadata::base(program, latency::NPROC, "latency")
+        }
+    }
+
+    fn base(program: &'static str, nproc: usize, mode: &'static str) -> Metadata {
+        let cpus = match affinity::current_cpus() {
+            Ok(cpus) => affinity::format_cpu_list(&cpus),
+            Err(e) => {
+                eprintln!("sched_getaffinity() failed: {}", e);
+                String::new()
+            }
+        };
+        Metadata {
+            program,
+            nproc,
+            total: None,
+            resol: None,
+            mode,
+            rounds: None,
+            latency_warmup: None,
+            workers: "processes",
+            loops_per_msec: None,
+            calibration_stddev: None,
+            cpus,
+            placement: Placement::Shared.name(),
+            policies: None,
+            nice: None,
+            cgroups: None,
+            environment: Fingerprint::collect(),
+        }
+    }
+
+    /// ループ回数の推定結果を記録します。
+    pub fn with_calibration(mut self, calibration: &Calibration) -> Metadata {
+        self.loops_per_msec = Some(calibration.loops_per_msec());
+        self.calibration_stddev = Some(calibration.relative_stddev());
+        self
+    }
+
+    /// 子プロセスの代わりにスレッドで負荷をかけたことを記録します。
+    pub fn with_threads(mut self) -> Metadata {
+        self.workers = "threads";
+        self
+    }
+
+    /// 子プロセスへの CPU の割り当て方を記録します。
+    pub fn with_placement(mut self, placement: Placement) -> Metadata {
+        self.placement = placement.name();
+        self
+    }
+
+    /// 子プロセスごとのスケジューリングポリシーを記録します。
+    pub fn with_policies(mut self, policies: &PolicyList) -> Metadata {
+        self.policies = Some(policies.to_string());
+        self
+    }
+
+    /// 子プロセスごとに指定した nice 値を記録します。
+    pub fn with_nice(mut self, nice: &NiceList) -> Metadata {
+        self.nice = Some(nice.to_string());
+        self
+    }
+
+    /// 子プロセスを入れた cgroup の設定を記録します。
+    pub fn with_cgroups(mut self, specs: &[GroupSpec]) -> Metadata {
+        self.cgroups = Some(cgroup::format_groups(specs));
+        self
+    }
+}
+
+/// 1 回分の記録
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Record {
+    /// プロセス番号
+    pub id: usize,
+    /// 開始からの経過時間（ms）
+    pub time_ms: usize,
+    /// 進捗（%）
+    pub progress: usize,
+    /// 消費した CPU 時間（ms）
+    pub cputime_ms: usize,
+    /// 動作していた CPU の番号
+    pub cpu: usize,
+    /// 子プロセスの nice 値（`getpriority()` で読み出した値）
+    pub nice: i32,
+    /// 開始からの経過時間（ns、出力しない）
+    #[serde(skip)]
+    pub elapsed_nsec: usize,
+    /// 消費した CPU 時間（ns、出力しない）
+    #[serde(skip)]
+    pub cputime_nsec: usize,
+    /// CPU 時間を計り始めた時点の、開始からの経過時間（ns、出力しない）
+    #[serde(skip)]
+    pub begin_nsec: usize,
+}
+
+impl Format {
+    /// メタデータを出力します。
+    pub fn write_header<W: Write, T: Serialize>(&self, w: &mut W, metadata: &T) -> io::Result<()> {
+        let value = serde_json::to_value(metadata)?;
+        match self {
+            Format::Tsv | Format::Csv => {
+                if let Value::Object(map) = &value {
+                    for (key, value) in map {
+                        writeln!(w, "# {}: {}", key, plain(value))?;
+                    }
+                }
+            }
+            Format::Jsonl => writeln!(w, "{}", tagged("metadata", value))?,
+        }
+        w.flush()
+    }
+
+    /// 記録をまとめて出力します。
+    pub fn write_records<W: Write>(&self, w: &mut W, records: &[Record]) -> io::Result<()> {
+        self.write_rows(w, "record", records)
+    }
+
+    /// 記録の後に付け加える情報を出力します。
+    ///
+    /// `tsv` と `csv` では記録の列と混ざらないよう `# <kind>: <列名>=<値> ...` 形式のコメント行として出力し、
+    /// `jsonl` では `type` に `kind` を設定したオブジェクトとして出力します。
+    pub fn write_footer<W: Write, T: Serialize>(
+        &self,
+        w: &mut W,
+        kind: &str,
+        rows: &[T],
+    ) -> io::Result<()> {
+        for row in rows {
+            let value = serde_json::to_value(row)?;
+            match self {
+                Format::Tsv | Format::Csv => {
+                    let fields = match &value {
+                        Value::Object(fields) => fields,
+                        _ => {
+                            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a struct"))
+                        }
+                    };
+                    let pairs = fields
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, plain(value)));
+                    writeln!(w, "# {}: {}", kind, join(pairs, " "))?
+                }
+                Format::Jsonl => writeln!(w, "{}", tagged(kind, value))?,
+            }
+        }
+        w.flush()
+    }
+
+    /// 構造体の各フィールドを列として、1 つの要素を 1 行に出力します。
+    /// `csv` では最初の行の前に列名を出力し、`jsonl` では `type` に `kind` を設定します。
+    pub fn write_rows<W: Write, T: Serialize>(
+        &self,
+        w: &mut W,
+        kind: &str,
+        rows: &[T],
+    ) -> io::Result<()> {
+        for (i, row) in rows.iter().enumerate() {
+            let value = serde_json::to_value(row)?;
+            let fields = match &value {
+                Value::Object(fields) => fields,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a struct")),
+            };
+            match self {
+                Format::Tsv => writeln!(w, "{}", join(fields.values().map(plain), "\t"))?,
+                Format::Csv => {
+                    if i == 0 {
+                        writeln!(w, "{}", join(fields.keys().map(|key| quoted(key)), ","))?;
+                    }
+                    let values = fields.values().map(|value| quoted(&plain(value)));
+                    writeln!(w, "{}", join(values, ","))?
+                }
+                Format::Jsonl => writeln!(w, "{}", tagged(kind, value))?,
+            }
+        }
+        w.flush()
+    }
+}
+
+fn join<I: Iterator<Item = String>>(items: I, sep: &str) -> String {
+    items.collect::<Vec<_>>().join(sep)
+}
+
+/// `tsv` や `csv` の 1 列分として、文字列を引用符なしで表示します。
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// `csv` の 1 列分として、カンマ、引用符、改行を含む値を引用符で囲みます。
+fn quoted(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// JSON オブジェクトの先頭に種類を表す `type` を付け加えます。
+fn tagged(kind: &str, value: Value) -> Value {
+    let mut map = Map::new();
+    map.insert("type".to_string(), Value::String(kind.to_string()));
+    if let Value::Object(fields) = value {
+        map.extend(fields);
+    }
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        id: usize,
+        name: &'static str,
+        value: Option<f64>,
+    }
+
+    const ROWS: [Row; 2] = [
+        Row {
+            id: 0,
+            name: "a",
+            value: Some(1.5),
+        },
+        Row {
+            id: 1,
+            name: "b",
+            value: None,
+        },
+    ];
+
+    fn written(f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut buf = Vec::new();
+        f(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn header_is_comment_lines_in_tsv_and_csv() {
+        for format in [Format::Tsv, Format::Csv] {
+            let out = written(|w| format.write_header(w, &ROWS[1]));
+            assert_eq!(out, "# id: 1\n# name: b\n# value: -\n");
+        }
+    }
+
+    #[test]
+    fn tsv_rows_have_no_column_names() {
+        let out = written(|w| Format::Tsv.write_rows(w, "row", &ROWS));
+        assert_eq!(out, "0\ta\t1.5\n1\tb\t-\n");
+    }
+
+    #[test]
+    fn csv_rows_start_with_column_names() {
+        let out = written(|w| Format::Csv.write_rows(w, "row", &ROWS));
+        assert_eq!(out, "id,name,value\n0,a,1.5\n1,b,-\n");
+        assert_eq!(
+            written(|w| Format::Csv.write_rows::<_, Row>(w, "row", &[])),
+            ""
+        );
+    }
+
+    #[test]
+    fn csv_quotes_fields_with_separators_and_quotes() {
+        #[derive(Serialize)]
+        struct Text {
+            text: &'static str,
+        }
+        let rows = [
+            Text { text: "0,2-3" },
+            Text { text: "say \"hi\"" },
+            Text { text: "two\nlines" },
+        ];
+        let out = written(|w| Format::Csv.write_rows(w, "text", &rows));
+        assert_eq!(out, "text\n\"0,2-3\"\n\"say \"\"hi\"\"\"\n\"two\nlines\"\n");
+    }
+
+    #[test]
+    fn footer_is_key_value_comment_lines_in_tsv_and_csv() {
+        for format in [Format::Tsv, Format::Csv] {
+            let out = written(|w| format.write_footer(w, "extra", &ROWS));
+            assert_eq!(
+                out,
+                "# extra: id=0 name=a value=1.5\n# extra: id=1 name=b value=-\n"
+            );
+        }
+    }
+
+    #[test]
+    fn jsonl_tags_each_line_with_its_kind() {
+        let out = written(|w| {
+            Format::Jsonl.write_header(w, &ROWS[0])?;
+            Format::Jsonl.write_rows(w, "row", &ROWS)?;
+            Format::Jsonl.write_footer(w, "extra", &ROWS[..1])
+        });
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"type":"metadata","id":0,"name":"a","value":1.5}"#,
+                r#"{"type":"row","id":0,"name":"a","value":1.5}"#,
+                r#"{"type":"row","id":1,"name":"b","value":null}"#,
+                r#"{"type":"extra","id":0,"name":"a","value":1.5}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn non_struct_rows_are_rejected() {
+        for format in [Format::Tsv, Format::Csv, Format::Jsonl] {
+            assert!(format.write_rows(&mut Vec::new(), "row", &[1, 2]).is_err());
+        }
+        assert!(Format::Tsv
+            .write_footer(&mut Vec::new(), "extra", &[1])
+            .is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! スケジューラの実験結果を SVG の図にする
+//!
+//! `sched.plt` で gnuplot を使って描いていた 2 つの図を、1 つの SVG の上下に並べて描きます。
+//!
+//! - 上: 各プロセスが負荷をかけ終えた時刻（横軸: 経過時間、縦軸: プロセス番号）
+//! - 下: 各プロセスの進捗（横軸: 経過時間、縦軸: 進捗）
+
+use crate::output::Record;
+use std::{fmt::Write, fs, io, path::Path};
+
+const WIDTH: f64 = 640.0;
+const PANEL_HEIGHT: f64 = 320.0;
+const MARGIN_LEFT: f64 = 60.0;
+const MARGIN_RIGHT: f64 = 110.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+
+/// プロセスごとの色（プロセス数が多い場合は繰り返す）
+const COLORS: [&str; 8] = [
+    "#9400d3", "#009e73", "#56b4e9", "#e69f00", "#f0e442", "#0072b2", "#e51e10", "#000000",
+];
+
+/// 図に描く 1 点
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    /// プロセス番号
+    pub id: usize,
+    /// 開始からの経過時間（ms）
+    pub time_ms: f64,
+    /// 進捗（%）
+    pub progress: f64,
+}
+
+impl From<&Record> for Point {
+    fn from(record: &Record) -> Self {
+        Point {
+            id: record.id,
+            time_ms: record.time_ms as f64,
+            progress: record.progress as f64,
+        }
+    }
+}
+
+/// 1 つの図の描画範囲
+struct Panel {
+    top: f64,
+    xmax: f64,
+    ymin: f64,
+    ymax: f64,
+}
+
+impl Panel {
+    fn x(&self, v: f64) -> f64 {
+        MARGIN_LEFT + v / self.xmax * (WIDTH - MARGIN_LEFT - MARGIN_RIGHT)
+    }
+
+    fn y(&self, v: f64) -> f64 {
+        let height = PANEL_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        self.top + MARGIN_TOP + (self.ymax - v) / (self.ymax - self.ymin) * height
+    }
+
+    /// 枠、目盛り、軸ラベルを描きます。
+    fn axes(&self, svg: &mut String, xlabel: &str, ylabel: &str, yticks: &[f64]) {
+        let (left, right) = (self.x(0.0), self.x(self.xmax));
+        let (top, bottom) = (self.y(self.ymax), self.y(self.ymin));
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="none" stroke="black"/>"#,
+            left,
+            top,
+            right - left,
+            bottom - top
+        );
+        for tick in ticks(self.xmax) {
+            let x = self.x(tick);
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{x:.1}" y1="{bottom:.1}" x2="{x:.1}" y2="{:.1}" stroke="black"/><text x="{x:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+                bottom - 5.0,
+                bottom + 18.0,
+                tick
+            );
+        }
+        for &tick in yticks {
+            let y = self.y(tick);
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{left:.1}" y1="{y:.1}" x2="{:.1}" y2="{y:.1}" stroke="black"/><text x="{:.1}" y="{:.1}" text-anchor="end">{}</text>"#,
+                left + 5.0,
+                left - 8.0,
+                y + 4.0,
+                tick
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+            (left + right) / 2.0,
+            bottom + 40.0,
+            xlabel
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text transform="translate({:.1},{:.1}) rotate(-90)" text-anchor="middle">{}</text>"#,
+            left - 40.0,
+            (top + bottom) / 2.0,
+            ylabel
+        );
+    }
+}
+
+/// 0 から `max` までを 10 個前後に区切る、1, 2, 5 の 10 のべき乗倍の目盛りを返します。
+fn ticks(max: f64) -> Vec<f64> {
+    let raw = max / 10.0;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= raw)
+        .unwrap_or(magnitude * 10.0);
+    (0..)
+        .map(|i| i as f64 * step)
+        .take_while(|&t| t <= max + step * 1e-9)
+        .collect()
+}
+
+/// 記録を SVG の図にします。
+pub fn render(points: &[Point], title: &str) -> String {
+    let nproc = points.iter().map(|p| p.id + 1).max().unwrap_or(1);
+    let xmax = points
+        .iter()
+        .map(|p| p.time_ms)
+        .fold(1.0, f64::max)
+        .max(1.0);
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="12">"#,
+        WIDTH,
+        PANEL_HEIGHT * 2.0
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="white"/><text x="{}" y="20" text-anchor="middle" font-size="14">{}</text>"#,
+        WIDTH / 2.0,
+        escape(title)
+    );
+
+    // プロセス番号と時刻の散布図
+    let scatter = Panel {
+        top: 0.0,
+        xmax,
+        ymin: -1.0,
+        ymax: nproc as f64,
+    };
+    let ids: Vec<f64> = (0..nproc).map(|id| id as f64).collect();
+    scatter.axes(&mut svg, "t(ms)", "process number", &ids);
+    for p in points {
+        let _ = writeln!(
+            svg,
+            r#"<circle cx="{:.1}" cy="{:.1}" r="2" fill="{}"/>"#,
+            scatter.x(p.time_ms),
+            scatter.y(p.id as f64),
+            COLORS[p.id % COLORS.len()]
+        );
+    }
+
+    // 進捗の折れ線
+    let progress = Panel {
+        top: PANEL_HEIGHT,
+        xmax,
+        ymin: 0.0,
+        ymax: 100.0,
+    };
+    progress.axes(
+        &mut svg,
+        "t(ms)",
+        "progress(%)",
+        &[0.0, 25.0, 50.0, 75.0, 100.0],
+    );
+    for id in 0..nproc {
+        let color = COLORS[id % COLORS.len()];
+        let line: Vec<String> = points
+            .iter()
+            .filter(|p| p.id == id)
+            .map(|p| format!("{:.1},{:.1}", progress.x(p.time_ms), progress.y(p.progress)))
+            .collect();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = writeln!(
+            svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+            line.join(" "),
+            color
+        );
+        let legend_y = PANEL_HEIGHT + MARGIN_TOP + 10.0 + id as f64 * 18.0;
+        let legend_x = WIDTH - MARGIN_RIGHT + 15.0;
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{}" stroke-width="3"/><text x="{:.1}" y="{:.1}">process {}</text>"#,
+            legend_x,
+            legend_y,
+            legend_x + 20.0,
+            legend_y,
+            color,
+            legend_x + 25.0,
+            legend_y + 4.0,
+            id
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// SVG のテキストとして使えない文字を置き換えます。
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// `sched` や `sched_nice` の出力（tsv, csv または jsonl）から図に描く点を読み取ります。
+///
+/// 先頭の 3 列（プロセス番号, 経過時間, 進捗）だけを使うため、列の少ない以前の形式の出力も読めます。
+pub fn parse_points(text: &str) -> Result<Vec<Point>, String> {
+    let mut points = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        // コメント（実験条件）、空行、csv の見出し行は読み飛ばす
+        if line.is_empty() || line.starts_with('#') || line.starts_with("id,") {
+            continue;
+        }
+        let point = if line.starts_with('{') {
+            match parse_json(line) {
+                Ok(Some(point)) => point,
+                Ok(None) => continue,
+                Err(e) => return Err(format!("line {}: {}", i + 1, e)),
+            }
+        } else {
+            parse_columns(line).map_err(|e| format!("line {}: {}", i + 1, e))?
+        };
+        points.push(point);
+    }
+    Ok(points)
+}
+
+fn parse_columns(line: &str) -> Result<Point, String> {
+    let fields: Vec<&str> = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|field| !field.is_empty())
+        .collect();
+    if fields.len() < 3 {
+        return Err(format!("expected at least 3 columns: {:?}", line));
+    }
+    let number = |field: &str| {
+        field
+            .parse::<f64>()
+            .map_err(|e| format!("{:?} should be number: {}", field, e))
+    };
+    Ok(Point {
+        id: fields[0]
+            .parse()
+            .map_err(|e| format!("{:?} should be process number: {}", fields[0], e))?,
+        time_ms: number(fields[1])?,
+        progress: number(fields[2])?,
+    })
+}
+
+/// jsonl の 1 行を読み取ります。記録以外の行（実験条件など）は `None` を返します。
+fn parse_json(line: &str) -> Result<Option<Point>, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if value["type"] != "record" {
+        return Ok(None);
+    }
+    let field = |name: &str| {
+        value[name]
+            .as_f64()
+            .ok_or_else(|| format!("missing field `{}`", name))
+    };
+    Ok(Some(Point {
+        id: field("id")? as usize,
+        time_ms: field("time_ms")?,
+        progress: field("progress")?,
+    }))
+}
+
+/// 記録を SVG の図にしてファイルに書き出します。
+pub fn write_svg(path: &Path, records: &[Record], title: &str) -> io::Result<()> {
+    let points: Vec<Point> = records.iter().map(Point::from).collect();
+    fs::write(path, render(&points, title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: usize, time_ms: f64, progress: f64) -> Point {
+        Point {
+            id,
+            time_ms,
+            progress,
+        }
+    }
+
+    #[test]
+    fn parse_points_reads_tsv_and_skips_metadata() {
+        let text = "# program: sched\n# nproc: 2\n\n0\t1\t50\t1\t0\t0\n1\t2\t50\t1\t0\t0\n";
+        assert_eq!(
+            parse_points(text),
+            Ok(vec![point(0, 1.0, 50.0), point(1, 2.0, 50.0)])
+        );
+    }
+
+    #[test]
+    fn parse_points_reads_old_three_column_output() {
+        assert_eq!(
+            parse_points("0\t10\t100\n"),
+            Ok(vec![point(0, 10.0, 100.0)])
+        );
+    }
+
+    #[test]
+    fn parse_points_reads_csv_with_header() {
+        let text = "# program: sched\nid,time_ms,progress,cputime_ms,cpu,nice\n1,3,25,3,0,0\n";
+        assert_eq!(parse_points(text), Ok(vec![point(1, 3.0, 25.0)]));
+    }
+
+    #[test]
+    fn parse_points_reads_only_records_from_jsonl() {
+        let text = concat!(
+            r#"{"type":"metadata","program":"sched"}"#,
+            "\n",
+            r#"{"type":"record","id":0,"time_ms":4,"progress":100}"#,
+            "\n",
+            r#"{"type":"schedstat","id":0,"run_ns":1}"#,
+            "\n",
+        );
+        assert_eq!(parse_points(text), Ok(vec![point(0, 4.0, 100.0)]));
+    }
+
+    #[test]
+    fn parse_points_reports_line_of_malformed_input() {
+        let err = parse_points("0\t1\t50\n0\t1\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        let err = parse_points("x\t1\t50\n").unwrap_err();
+        assert!(err.starts_with("line 1:"), "{}", err);
+        let err = parse_points(r#"{"type":"record","id":0}"#).unwrap_err();
+        assert!(err.contains("time_ms"), "{}", err);
+    }
+}
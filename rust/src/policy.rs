@@ -0,0 +1,298 @@
+//! スケジューリングポリシーの設定
+//!
+//! `--policy 0:fifo:10,1:other` のように、子プロセスごとにポリシーと優先度を指定します。
+//! 指定しなかった子プロセスは親プロセスのポリシー（通常は `SCHED_OTHER`）のまま動きます。
+//!
+//! `fifo` と `rr` は実時間ポリシーのため、`CAP_SYS_NICE` を持つか、
+//! `RLIMIT_RTPRIO` が優先度以上である必要があります。
+
+use nix::{errno::Errno, libc};
+use std::{fmt, fs, str::FromStr};
+
+/// `/proc/self/status` の `CapEff` における `CAP_SYS_NICE` のビット位置
+const CAP_SYS_NICE: u32 = 23;
+
+/// スケジューリングポリシー
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// `SCHED_OTHER`（CFS による通常のスケジューリング）
+    Other,
+    /// `SCHED_FIFO`（実時間、タイムスライスなし）
+    Fifo,
+    /// `SCHED_RR`（実時間、ラウンドロビン）
+    Rr,
+    /// `SCHED_BATCH`（CPU を使い続けるバッチ処理向け）
+    Batch,
+    /// `SCHED_IDLE`（他に動くものがないときだけ動く）
+    Idle,
+}
+
+impl Policy {
+    fn raw(&self) -> libc::c_int {
+        match self {
+            Policy::Other => libc::SCHED_OTHER,
+            Policy::Fifo => libc::SCHED_FIFO,
+            Policy::Rr => libc::SCHED_RR,
+            Policy::Batch => libc::SCHED_BATCH,
+            Policy::Idle => libc::SCHED_IDLE,
+        }
+    }
+
+    /// 実時間ポリシーかどうか
+    pub fn is_realtime(&self) -> bool {
+        matches!(self, Policy::Fifo | Policy::Rr)
+    }
+
+    /// 指定できる優先度の範囲
+    pub fn priority_range(&self) -> nix::Result<(i32, i32)> {
+        let min = Errno::result(unsafe { libc::sched_get_priority_min(self.raw()) })?;
+        let max = Errno::result(unsafe { libc::sched_get_priority_max(self.raw()) })?;
+        Ok((min, max))
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "other" => Ok(Policy::Other),
+            "fifo" => Ok(Policy::Fifo),
+            "rr" => Ok(Policy::Rr),
+            "batch" => Ok(Policy::Batch),
+            "idle" => Ok(Policy::Idle),
+            _ => Err(format!(
+                "policy should be 'other', 'fifo', 'rr', 'batch' or 'idle': {}",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Policy::Other => "other",
+            Policy::Fifo => "fifo",
+            Policy::Rr => "rr",
+            Policy::Batch => "batch",
+            Policy::Idle => "idle",
+        })
+    }
+}
+
+/// 1 つの子プロセスに対するポリシーの指定（`<id>:<policy>[:<priority>]`）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PolicySpec {
+    /// 子プロセスの番号
+    pub id: usize,
+    pub policy: Policy,
+    /// 優先度（実時間ポリシー以外は 0）
+    pub priority: i32,
+}
+
+impl PolicySpec {
+    /// 呼び出したプロセスのポリシーと優先度を変更します。
+    pub fn apply(&self) -> nix::Result<()> {
+        let param = libc::sched_param {
+            sched_priority: self.priority,
+        };
+        Errno::result(unsafe { libc::sched_setscheduler(0, self.policy.raw(), &param) }).map(drop)
+    }
+
+    /// このプロセスの権限でポリシーを設定できるかを確かめます。
+    pub fn check_permission(&self) -> Result<(), String> {
+        if !self.policy.is_realtime() || has_cap_sys_nice() {
+            return Ok(());
+        }
+        let limit = rtprio_limit();
+        if limit >= self.priority as u64 {
+            return Ok(());
+        }
+        Err(format!(
+            "child {}: SCHED_{} with priority {} requires CAP_SYS_NICE or RLIMIT_RTPRIO >= {} (current: {}); run as root or raise the limit with `ulimit -r`",
+            self.id,
+            self.policy.to_string().to_uppercase(),
+            self.priority,
+            self.priority,
+            limit
+        ))
+    }
+}
+
+impl FromStr for PolicySpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split(':').collect();
+        let (id, policy, priority) = match fields[..] {
+            [id, policy] => (id, policy, None),
+            [id, policy, priority] => (id, policy, Some(priority)),
+            _ => return Err(format!("should be <id>:<policy>[:<priority>]: {}", s)),
+        };
+        let id = id
+            .parse::<usize>()
+            .map_err(|e| format!("invalid child id {:?}: {}", id, e))?;
+        let policy = policy.parse::<Policy>()?;
+        let priority = match priority {
+            Some(p) => p
+                .parse::<i32>()
+                .map_err(|e| format!("invalid priority {:?}: {}", p, e))?,
+            None if policy.is_realtime() => 1,
+            None => 0,
+        };
+        let (min, max) = policy
+            .priority_range()
+            .map_err(|e| format!("sched_get_priority_min/max() failed: {}", e))?;
+        if !(min..=max).contains(&priority) {
+            return Err(format!(
+                "priority of {} should be {}..={}: {}",
+                policy, min, max, priority
+            ));
+        }
+        Ok(PolicySpec {
+            id,
+            policy,
+            priority,
+        })
+    }
+}
+
+impl fmt::Display for PolicySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.id, self.policy, self.priority)
+    }
+}
+
+/// `--policy 0:fifo:10,1:other` のように指定する、子プロセスごとのポリシーの一覧
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolicyList(pub Vec<PolicySpec>);
+
+impl PolicyList {
+    /// `id` 番目の子プロセスに対する指定を返します。
+    pub fn for_child(&self, id: usize) -> Option<&PolicySpec> {
+        self.0.iter().find(|spec| spec.id == id)
+    }
+
+    /// 子プロセスの番号が `nproc` 未満であり、
+    /// このプロセスの権限ですべてのポリシーを設定できるかを確かめます。
+    pub fn check(&self, nproc: usize) -> Result<(), String> {
+        for spec in &self.0 {
+            if spec.id >= nproc {
+                return Err(format!(
+                    "child id in --policy should be < nproc({}): {}",
+                    nproc, spec
+                ));
+            }
+            spec.check_permission()?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PolicyList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut specs = Vec::new();
+        for spec in s.split(',') {
+            let spec = spec.parse::<PolicySpec>()?;
+            if specs.iter().any(|s: &PolicySpec| s.id == spec.id) {
+                return Err(format!("child {} is specified more than once", spec.id));
+            }
+            specs.push(spec);
+        }
+        Ok(PolicyList(specs))
+    }
+}
+
+impl fmt::Display for PolicyList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let specs: Vec<String> = self.0.iter().map(PolicySpec::to_string).collect();
+        f.write_str(&specs.join(","))
+    }
+}
+
+/// 実効ケーパビリティに `CAP_SYS_NICE` が含まれるかを返します。
+pub(crate) fn has_cap_sys_nice() -> bool {
+    let status = match fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << CAP_SYS_NICE) != 0)
+}
+
+/// `RLIMIT_RTPRIO` のソフトリミットを返します。
+fn rtprio_limit() -> u64 {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    match unsafe { libc::getrlimit(libc::RLIMIT_RTPRIO, &mut rlim) } {
+        0 => rlim.rlim_cur,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(id: usize, policy: Policy, priority: i32) -> PolicySpec {
+        PolicySpec {
+            id,
+            policy,
+            priority,
+        }
+    }
+
+    #[test]
+    fn policy_spec_fills_default_priority() {
+        assert_eq!("0:other".parse(), Ok(spec(0, Policy::Other, 0)));
+        assert_eq!("1:batch".parse(), Ok(spec(1, Policy::Batch, 0)));
+        assert_eq!("2:idle".parse(), Ok(spec(2, Policy::Idle, 0)));
+        assert_eq!("3:fifo".parse(), Ok(spec(3, Policy::Fifo, 1)));
+        assert_eq!("4:rr".parse(), Ok(spec(4, Policy::Rr, 1)));
+    }
+
+    #[test]
+    fn policy_spec_accepts_priority_in_range() {
+        assert_eq!("0:fifo:10".parse(), Ok(spec(0, Policy::Fifo, 10)));
+        assert_eq!("1:rr:99".parse(), Ok(spec(1, Policy::Rr, 99)));
+        assert_eq!("2:other:0".parse(), Ok(spec(2, Policy::Other, 0)));
+    }
+
+    #[test]
+    fn policy_spec_rejects_invalid_specs() {
+        for s in [
+            "0",
+            "0:",
+            "x:fifo",
+            "0:deadline",
+            "0:fifo:x",
+            "0:fifo:0",
+            "0:rr:100",
+            "0:other:5",
+            "0:fifo:1:2",
+        ] {
+            assert!(
+                s.parse::<PolicySpec>().is_err(),
+                "{:?} should be rejected",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn policy_list_round_trips_and_rejects_duplicates() {
+        let list: PolicyList = "0:fifo:10,1:other".parse().unwrap();
+        assert_eq!(list.to_string(), "0:fifo:10,1:other:0");
+        assert_eq!(list.for_child(1), Some(&spec(1, Policy::Other, 0)));
+        assert_eq!(list.for_child(2), None);
+        assert!("0:fifo,0:other".parse::<PolicyList>().is_err());
+    }
+}
@@ -0,0 +1,6 @@
+use clap::Parser;
+use playground::cli::sched_cgroup::{run, Args};
+
+fn main() {
+    run(Args::parse());
+}
@@ -0,0 +1,170 @@
+//! カーネルが記録している子プロセスごとのスケジューラの統計情報
+//!
+//! - `/proc/<pid>/schedstat`: CPU 上で動作した時間、実行可能状態で待たされた時間、CPU を割り当てられた回数
+//! - `/proc/<pid>/status`: 自発的・非自発的なコンテキストスイッチの回数
+//! - `/proc/<pid>/sched`: CPU 間の移動回数（`CONFIG_SCHED_DEBUG` が有効なカーネルのみ）
+
+use nix::unistd::Pid;
+use serde::Serialize;
+use std::fs;
+
+/// 子プロセス 1 つ分の統計情報
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedStat {
+    /// プロセス番号
+    pub id: usize,
+    /// CPU 上で動作した時間（ns）
+    pub run_ns: u64,
+    /// 実行可能状態のまま CPU を待っていた時間（ns）
+    pub wait_ns: u64,
+    /// CPU を割り当てられた回数
+    pub timeslices: u64,
+    /// 自発的なコンテキストスイッチ（スリープなど）の回数
+    pub voluntary_ctxt_switches: u64,
+    /// 非自発的なコンテキストスイッチ（プリエンプション）の回数
+    pub nonvoluntary_ctxt_switches: u64,
+    /// CPU 間を移動した回数（`/proc/<pid>/sched` が読めない場合は `None`）
+    pub migrations: Option<u64>,
+    /// 記録から求めた待ち時間（計測を始めてから最後の記録までの経過時間から、消費 CPU 時間を引いたもの, ns）
+    pub timeline_wait_ns: u64,
+}
+
+/// 終了した（回収前の）子プロセスの統計情報を読み出します。
+///
+/// `timeline_wait_ns` には記録から求めた待ち時間を渡します。
+pub fn read(id: usize, pid: Pid, timeline_wait_ns: u64) -> Result<SchedStat, String> {
+    let schedstat = read_file(pid, "schedstat")?;
+    let status = read_file(pid, "status")?;
+    let sched = read_file(pid, "sched").ok();
+    parse(id, &schedstat, &status, sched.as_deref(), timeline_wait_ns)
+        .map_err(|e| format!("/proc/{}/{}", pid, e))
+}
+
+/// `/proc/<pid>/schedstat`、`status`、（読めた場合は）`sched` の内容から統計情報を取り出します。
+///
+/// エラーのメッセージは、足りなかった項目を含むファイル名から始まります。
+pub fn parse(
+    id: usize,
+    schedstat: &str,
+    status: &str,
+    sched: Option<&str>,
+    timeline_wait_ns: u64,
+) -> Result<SchedStat, String> {
+    let mut fields = schedstat.split_whitespace().map(str::parse::<u64>);
+    let mut next = |name: &str| match fields.next() {
+        Some(Ok(value)) => Ok(value),
+        _ => Err(format!(
+            "schedstat: missing {}: {:?}",
+            name,
+            schedstat.trim()
+        )),
+    };
+    let run_ns = next("run time")?;
+    let wait_ns = next("wait time")?;
+    let timeslices = next("timeslices")?;
+
+    let ctxt_switches =
+        |key: &str| field(status, key).ok_or_else(|| format!("status: missing {}", key));
+
+    Ok(SchedStat {
+        id,
+        run_ns,
+        wait_ns,
+        timeslices,
+        voluntary_ctxt_switches: ctxt_switches("voluntary_ctxt_switches")?,
+        nonvoluntary_ctxt_switches: ctxt_switches("nonvoluntary_ctxt_switches")?,
+        migrations: sched.and_then(|sched| field(sched, "se.nr_migrations")),
+        timeline_wait_ns,
+    })
+}
+
+fn read_file(pid: Pid, name: &str) -> Result<String, String> {
+    let path = format!("/proc/{}/{}", pid, name);
+    fs::read_to_string(&path).map_err(|e| format!("{}: read() failed: {}", path, e))
+}
+
+/// `key: value` 形式の行から `key` の値を取り出します。
+fn field(text: &str, key: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let (k, v) = line.split_once(':')?;
+        if k.trim() == key {
+            v.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEDSTAT: &str = "1204562311 3571209 42\n";
+    const STATUS: &str = "\
+Name:\tsched
+State:\tZ (zombie)
+Tgid:\t1234
+voluntary_ctxt_switches:\t3
+nonvoluntary_ctxt_switches:\t117
+";
+    const SCHED: &str = "\
+sched (1234, #threads: 1)
+-------------------------------------------------------------------
+se.exec_start                                :      12345678.901234
+se.nr_migrations                             :                    5
+nr_switches                                  :                  120
+";
+
+    #[test]
+    fn parse_reads_each_file() {
+        assert_eq!(
+            parse(2, SCHEDSTAT, STATUS, Some(SCHED), 777),
+            Ok(SchedStat {
+                id: 2,
+                run_ns: 1204562311,
+                wait_ns: 3571209,
+                timeslices: 42,
+                voluntary_ctxt_switches: 3,
+                nonvoluntary_ctxt_switches: 117,
+                migrations: Some(5),
+                timeline_wait_ns: 777,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_leaves_migrations_empty_without_sched() {
+        let stat = parse(0, SCHEDSTAT, STATUS, None, 0).unwrap();
+        assert_eq!(stat.migrations, None);
+        // CONFIG_SCHED_DEBUG の出力形式が変わって読めない場合も同じ
+        let stat = parse(0, SCHEDSTAT, STATUS, Some("nr_switches: 1\n"), 0).unwrap();
+        assert_eq!(stat.migrations, None);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_schedstat() {
+        for schedstat in ["", "1 2", "1 x 3", "-1 2 3"] {
+            let e = parse(0, schedstat, STATUS, None, 0).unwrap_err();
+            assert!(
+                e.starts_with("schedstat: missing"),
+                "{:?}: {}",
+                schedstat,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_status_without_ctxt_switches() {
+        let status = "Name:\tsched\nvoluntary_ctxt_switches:\t3\n";
+        assert_eq!(
+            parse(0, SCHEDSTAT, status, None, 0),
+            Err("status: missing nonvoluntary_ctxt_switches".to_string())
+        );
+        let status = "voluntary_ctxt_switches:\tmany\nnonvoluntary_ctxt_switches:\t1\n";
+        assert_eq!(
+            parse(0, SCHEDSTAT, status, None, 0),
+            Err("status: missing voluntary_ctxt_switches".to_string())
+        );
+    }
+}
@@ -0,0 +1,629 @@
+//! スケジューラの実験で各子プロセスが実行する処理
+//!
+//! 子プロセスは記録を標準出力に直接書かず、プロセスごとに作ったパイプで親プロセスに送ります。
+//! 親プロセスは全員分の記録を集めてから時刻順に並べ、まとめて出力します。
+
+use crate::{
+    affinity::{self, current_cpu, Placement},
+    clock::{diff_nsec, get_cputime, get_time, NSECS_PER_MSEC},
+    load::{load, load_cputime},
+    nice::{get_nice, set_nice, NiceList},
+    output::Record,
+    policy::PolicyList,
+    schedstat::{self, SchedStat},
+};
+use anyhow::{anyhow, bail, Context};
+use clap::ArgEnum;
+use nix::{
+    errno::Errno,
+    libc::{self, EXIT_FAILURE, EXIT_SUCCESS},
+    sys::{
+        signal::{kill, Signal::SIGINT},
+        time::TimeSpec,
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{close, fork, gettid, pipe, ForkResult, Pid},
+};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    os::unix::io::FromRawFd,
+    thread,
+};
+
+/// 1回の計測ごとにかける負荷の決め方
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadMode {
+    /// 事前に推定したループ回数だけ回す
+    Loops,
+    /// 自スレッドの CPU 時間が解像度分進むまで回す
+    Cputime,
+}
+
+/// 解像度 1 回分の負荷
+#[derive(Clone, Copy, Debug)]
+pub enum Workload {
+    /// 指定回数のループ
+    Loops(usize),
+    /// 指定した CPU 時間（ns）
+    Cputime(usize),
+}
+
+impl Workload {
+    /// 解像度 1 回分の負荷をかけます。
+    #[inline]
+    pub fn run(&self) {
+        match *self {
+            Workload::Loops(nloop) => load(nloop),
+            Workload::Cputime(nsec) => load_cputime(nsec),
+        }
+    }
+}
+
+/// 解像度 1 回分の負荷をかけ終えた時点の時刻
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    /// 経過時間（`CLOCK_MONOTONIC`）
+    pub wall: TimeSpec,
+    /// 自スレッドが消費した CPU 時間（`CLOCK_THREAD_CPUTIME_ID`）
+    pub cpu: TimeSpec,
+    /// 負荷をかけ終えた時点で動作していた CPU の番号
+    pub cpu_id: usize,
+}
+
+/// 負荷を `nrecord` 回かけ、そのたびに時刻を記録します。
+pub fn run_workload(workload: Workload, nrecord: usize) -> Result<Vec<Sample>, String> {
+    let mut buf = Vec::with_capacity(nrecord);
+    for _ in 0..nrecord {
+        workload.run();
+        let cpu_id = current_cpu().map_err(|e| format!("sched_getcpu() failed: {}", e))?;
+        buf.push(Sample {
+            wall: get_time(),
+            cpu: get_cputime(),
+            cpu_id,
+        });
+    }
+    Ok(buf)
+}
+
+/// 子プロセスが負荷をかけ始める前に行う設定
+#[derive(Clone, Debug)]
+pub struct ChildSetup {
+    /// 子プロセスを動かす CPU
+    pub cpus: Vec<usize>,
+    /// 子プロセスへの CPU の割り当て方
+    pub placement: Placement,
+    /// 子プロセスごとのスケジューリングポリシー
+    pub policies: Option<PolicyList>,
+    /// 子プロセスごとの nice 値
+    pub nice: Option<NiceList>,
+}
+
+impl ChildSetup {
+    /// `id` 番目の子プロセスとして、呼び出したプロセス（またはスレッド）に設定を適用します。
+    pub fn apply(&self, id: usize) -> Result<(), String> {
+        let cpus = self.placement.cpus_for(id, &self.cpus);
+        affinity::set_cpus(&cpus).map_err(|e| format!("sched_setaffinity() failed: {}", e))?;
+        if let Some(spec) = self.policies.as_ref().and_then(|p| p.for_child(id)) {
+            spec.apply().map_err(|e| {
+                format!(
+                    "sched_setscheduler({}, {}) failed: {}",
+                    spec.policy, spec.priority, e
+                )
+            })?;
+        }
+        if let Some(nice) = self.nice.as_ref().and_then(|n| n.for_child(id)) {
+            set_nice(nice).map_err(|e| format!("setpriority({}) failed: {}", nice, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// 子プロセスが親プロセスに送る 1 回分の計測結果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Measurement {
+    /// 子プロセスが計測を始めた時点の、実験開始からの経過時間（ns）
+    begin_nsec: usize,
+    /// 実験開始からの経過時間（ns）
+    elapsed_nsec: usize,
+    /// 子プロセスが消費した CPU 時間（ns）
+    cputime_nsec: usize,
+    /// 動作していた CPU の番号
+    cpu: usize,
+    /// 子プロセスの nice 値
+    nice: i32,
+}
+
+impl Measurement {
+    /// パイプに書き出す 1 行の形式（タブ区切り）
+    fn to_line(self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.begin_nsec, self.elapsed_nsec, self.cputime_nsec, self.cpu, self.nice
+        )
+    }
+
+    /// [`Measurement::to_line`] で書き出した 1 行を解釈します。
+    fn parse(line: &str) -> Option<Measurement> {
+        let mut fields = line.split('\t');
+        let measurement = Measurement {
+            begin_nsec: fields.next()?.parse().ok()?,
+            elapsed_nsec: fields.next()?.parse().ok()?,
+            cputime_nsec: fields.next()?.parse().ok()?,
+            cpu: fields.next()?.parse().ok()?,
+            nice: fields.next()?.parse().ok()?,
+        };
+        match fields.next() {
+            None => Some(measurement),
+            Some(_) => None,
+        }
+    }
+}
+
+/// 負荷を `nrecord` 回かけ、`start` からの経過時間などを計測結果として返します。
+///
+/// 消費 CPU 時間は計測を始めた時点からの値なので、経過時間と比べるときは `begin_nsec` を引きます。
+fn measure(
+    workload: Workload,
+    nrecord: usize,
+    start: TimeSpec,
+) -> Result<Vec<Measurement>, String> {
+    let nice = get_nice().map_err(|e| format!("getpriority() failed: {}", e))?;
+    let begin_nsec = diff_nsec(start, get_time());
+    let cpu_start = get_cputime();
+    let samples = run_workload(workload, nrecord)?;
+    Ok(samples
+        .into_iter()
+        .map(|sample| Measurement {
+            begin_nsec,
+            elapsed_nsec: diff_nsec(start, sample.wall),
+            cputime_nsec: diff_nsec(cpu_start, sample.cpu),
+            cpu: sample.cpu_id,
+            nice,
+        })
+        .collect())
+}
+
+/// 記録から求めた待ち時間（計測を始めてから最後の記録までの経過時間から、消費 CPU 時間を引いたもの）
+fn timeline_wait_ns(measurements: &[Measurement]) -> u64 {
+    measurements.last().map_or(0, |m| {
+        (m.elapsed_nsec - m.begin_nsec).saturating_sub(m.cputime_nsec) as u64
+    })
+}
+
+/// 子プロセスの処理
+///
+/// 負荷をかけ終えてから、記録した時刻を `out` に 1 行ずつ書き出し終了します。
+/// 設定や計測に失敗した場合は、エラーを表示して異常終了し、親プロセスが回収するときに失敗として扱われます。
+fn child_fn(id: usize, measured: Result<Vec<Measurement>, String>, out: File) -> ! {
+    let measurements = match measured {
+        Ok(measurements) => measurements,
+        Err(e) => {
+            eprintln!("child {}: {}", id, e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let mut out = BufWriter::new(out);
+    for measurement in measurements {
+        if let Err(e) = writeln!(out, "{}", measurement.to_line()) {
+            eprintln!("write() failed: {}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+    if let Err(e) = out.flush() {
+        eprintln!("write() failed: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    }
+    std::process::exit(EXIT_SUCCESS);
+}
+
+/// 子プロセスから送られた計測結果をパイプが閉じられるまで読み出します。
+fn read_measurements(input: &File) -> anyhow::Result<Vec<Measurement>> {
+    let mut measurements = Vec::new();
+    for line in BufReader::new(input).lines() {
+        let line = line.context("read() failed")?;
+        match Measurement::parse(&line) {
+            Some(measurement) => measurements.push(measurement),
+            None => bail!("malformed record: {:?}", line),
+        }
+    }
+    Ok(measurements)
+}
+
+/// 実験中の子プロセス
+pub(crate) struct Child {
+    /// プロセス番号
+    pub(crate) id: usize,
+    /// プロセス ID
+    pub(crate) pid: Pid,
+    /// 子プロセスが結果を書き出すパイプの読み出し側
+    pub(crate) input: File,
+}
+
+impl Child {
+    /// 子プロセスが終了するまで待ちます。回収はしないので、`/proc/<pid>` は読めるままです。
+    pub(crate) fn wait_exit(&self) -> nix::Result<()> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                self.pid.as_raw() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        Errno::result(ret).map(drop)
+    }
+
+    /// 子プロセスの終了を待ち、正常に終了しなかった場合はその理由を返します。
+    pub(crate) fn reap(&self) -> Result<(), String> {
+        match waitpid(self.pid, None) {
+            Ok(WaitStatus::Exited(_, EXIT_SUCCESS)) => Ok(()),
+            Ok(WaitStatus::Exited(_, status)) => Err(format!(
+                "child {} (pid {}) exited with status {}",
+                self.id, self.pid, status
+            )),
+            Ok(WaitStatus::Signaled(_, signal, core_dumped)) => Err(format!(
+                "child {} (pid {}) was killed by {}{}",
+                self.id,
+                self.pid,
+                signal,
+                if core_dumped { " (core dumped)" } else { "" }
+            )),
+            Ok(status) => Err(format!(
+                "child {} (pid {}) stopped unexpectedly: {:?}",
+                self.id, self.pid, status
+            )),
+            Err(e) => Err(format!(
+                "wait() for child {} (pid {}) failed: {}",
+                self.id, self.pid, e
+            )),
+        }
+    }
+}
+
+/// 作成済みの子プロセスに `SIGINT` を送り、すべて回収します。
+pub(crate) fn abort(children: &[Child]) {
+    for child in children {
+        if let Err(e) = kill(child.pid, SIGINT) {
+            eprintln!("kill({}) failed: {}", child.pid, e);
+        }
+    }
+    for child in children {
+        if let Err(e) = waitpid(child.pid, None) {
+            eprintln!(
+                "wait() for child {} (pid {}) failed: {}",
+                child.id, child.pid, e
+            );
+        }
+    }
+}
+
+/// 子プロセスを `nproc` 個作ります。
+///
+/// 子プロセスごとにパイプを作り、各子プロセスはプロセス番号とパイプの書き込み側を引数にして `body` を呼び出します。
+/// `body` から戻った子プロセスはそのまま終了します。
+/// 子プロセスを作れなかった場合は、作成済みの子プロセスを終了させてからエラーを返します。
+pub(crate) fn spawn<F>(nproc: usize, body: F) -> anyhow::Result<Vec<Child>>
+where
+    F: Fn(usize, File),
+{
+    let mut children = Vec::<Child>::with_capacity(nproc);
+
+    for id in 0..nproc {
+        let (read_fd, write_fd) = match pipe() {
+            Ok(fds) => fds,
+            Err(e) => {
+                abort(&children);
+                bail!("pipe() failed: {}", e);
+            }
+        };
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child, .. }) => {
+                // 書き込み側を閉じておかないと、子プロセスが終了してもEOFにならない
+                if let Err(e) = close(write_fd) {
+                    eprintln!("close() failed: {}", e);
+                }
+                children.push(Child {
+                    id,
+                    pid: child,
+                    input: unsafe { File::from_raw_fd(read_fd) },
+                });
+            }
+            Ok(ForkResult::Child) => {
+                let _ = close(read_fd);
+                body(id, unsafe { File::from_raw_fd(write_fd) });
+                std::process::exit(EXIT_SUCCESS);
+            }
+            Err(e) => {
+                let _ = close(read_fd);
+                let _ = close(write_fd);
+                abort(&children);
+                bail!(
+                    "fork() failed after creating {} of {} children: {}",
+                    children.len(),
+                    nproc,
+                    e
+                );
+            }
+        }
+    }
+    Ok(children)
+}
+
+/// 実験の結果
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    /// 全員分の記録（経過時間の順）
+    pub records: Vec<Record>,
+    /// 子プロセスごとのスケジューラの統計情報（読み出すよう指定した場合の、読み出せた子プロセスのみ）
+    pub schedstats: Vec<SchedStat>,
+    /// 子プロセス（またはスレッド）をすべて作るのにかかった時間（ns）
+    pub creation_nsec: usize,
+}
+
+/// 1 つの子プロセス分の計測結果を記録に変換して `records` に加えます。
+fn push_records(
+    records: &mut Vec<(usize, Record)>,
+    id: usize,
+    measurements: &[Measurement],
+    nrecord: usize,
+) {
+    for (i, m) in measurements.iter().enumerate() {
+        records.push((
+            m.elapsed_nsec,
+            Record {
+                id,
+                time_ms: m.elapsed_nsec / NSECS_PER_MSEC,
+                progress: (i + 1) * 100 / nrecord,
+                cputime_ms: m.cputime_nsec / NSECS_PER_MSEC,
+                cpu: m.cpu,
+                nice: m.nice,
+                elapsed_nsec: m.elapsed_nsec,
+                cputime_nsec: m.cputime_nsec,
+                begin_nsec: m.begin_nsec,
+            },
+        ));
+    }
+}
+
+impl Timeline {
+    /// 全員分の記録を経過時間の順に並べて結果にまとめます。
+    fn new(
+        mut records: Vec<(usize, Record)>,
+        schedstats: Vec<SchedStat>,
+        creation_nsec: usize,
+    ) -> Timeline {
+        records.sort_by_key(|&(elapsed_nsec, record)| (elapsed_nsec, record.id));
+        Timeline {
+            records: records.into_iter().map(|(_, record)| record).collect(),
+            schedstats,
+            creation_nsec,
+        }
+    }
+
+    /// `id` 番目の子プロセスが負荷をかけ終えるまでの経過時間（ms、記録がなければ 0）
+    pub fn finish_msec(&self, id: usize) -> f64 {
+        self.records
+            .iter()
+            .rev()
+            .find(|record| record.id == id)
+            .map_or(0.0, |record| {
+                record.elapsed_nsec as f64 / NSECS_PER_MSEC as f64
+            })
+    }
+}
+
+/// 子プロセスを `nproc` 個作り、それぞれに `workload` を `nrecord` 回実行させます。
+///
+/// 各子プロセスは、負荷をかけ始める前に `setup` をプロセス番号を引数にして呼び出し、失敗した場合は異常終了します。
+/// 全員分の記録を集め、経過時間の順に並べて返します。
+/// `schedstat` が真なら、各子プロセスの終了後、回収する前にカーネルが記録した統計情報も読み出します。
+///
+/// 子プロセスを作れなかった場合は、作成済みの子プロセスを終了させてからエラーを返します。
+/// 記録をすべて送り終える前に終了したり、異常終了したりした子プロセスがあった場合もエラーを返します。
+pub fn run<F>(
+    nproc: usize,
+    workload: Workload,
+    nrecord: usize,
+    schedstat: bool,
+    setup: F,
+) -> anyhow::Result<Timeline>
+where
+    F: Fn(usize) -> Result<(), String>,
+{
+    let start = get_time();
+    let children = spawn(nproc, |id, out| {
+        let measured = setup(id).and_then(|()| measure(workload, nrecord, start));
+        child_fn(id, measured, out)
+    })?;
+    let creation_nsec = diff_nsec(start, get_time());
+
+    let mut records = Vec::<(usize, Record)>::with_capacity(nproc * nrecord);
+    let mut schedstats = Vec::with_capacity(nproc);
+    let mut errors = Vec::new();
+    for child in &children {
+        // パイプが閉じられるのは子プロセスが終了したときなので、読み終えてから回収する
+        let delivered = read_measurements(&child.input);
+        // 回収すると /proc/<pid> が消えるので、終了を待ってから統計情報を読み出す
+        if schedstat {
+            match child.wait_exit() {
+                Ok(()) => {
+                    let wait_ns = delivered.as_deref().map_or(0, timeline_wait_ns);
+                    match schedstat::read(child.id, child.pid, wait_ns) {
+                        Ok(stat) => schedstats.push(stat),
+                        Err(e) => eprintln!("child {}: {}", child.id, e),
+                    }
+                }
+                Err(e) => eprintln!(
+                    "waitid() for child {} (pid {}) failed: {}",
+                    child.id, child.pid, e
+                ),
+            }
+        }
+        if let Err(e) = child.reap() {
+            errors.push(e);
+            continue;
+        }
+        match delivered {
+            Ok(measurements) if measurements.len() == nrecord => {
+                push_records(&mut records, child.id, &measurements, nrecord)
+            }
+            Ok(measurements) => errors.push(format!(
+                "child {} (pid {}) exited before delivering its records ({} of {})",
+                child.id,
+                child.pid,
+                measurements.len(),
+                nrecord
+            )),
+            Err(e) => errors.push(format!("child {} (pid {}): {:#}", child.id, child.pid, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!(errors.join("\n")));
+    }
+
+    Ok(Timeline::new(records, schedstats, creation_nsec))
+}
+
+/// [`run`] と同じ実験を、子プロセスの代わりに 1 つのプロセス内のスレッドで行います。
+///
+/// 各スレッドは、負荷をかけ始める前に `setup` をスレッド番号を引数にして呼び出します。
+/// 設定や計測に失敗したスレッドはプロセスを終了させずにエラーを返し、ほかのスレッドが終わるのを待ってからまとめて報告します。
+/// CPU の割り当て、スケジューリングポリシー、nice 値はいずれもスレッドごとに設定されます。
+/// `schedstat` が真なら、各スレッドが負荷をかけ終えた時点で自分の分の統計情報を読み出します。
+pub fn run_threads<F>(
+    nproc: usize,
+    workload: Workload,
+    nrecord: usize,
+    schedstat: bool,
+    setup: F,
+) -> anyhow::Result<Timeline>
+where
+    F: Fn(usize) -> Result<(), String> + Sync,
+{
+    let start = get_time();
+    let (results, creation_nsec) = thread::scope(|scope| {
+        let mut handles = Vec::with_capacity(nproc);
+        let mut errors = Vec::new();
+        for id in 0..nproc {
+            let setup = &setup;
+            let spawned = thread::Builder::new()
+                .name(format!("child {}", id))
+                .spawn_scoped(scope, move || {
+                    setup(id)?;
+                    let measurements = measure(workload, nrecord, start)?;
+                    let stat = schedstat
+                        .then(|| schedstat::read(id, gettid(), timeline_wait_ns(&measurements)));
+                    Ok::<_, String>((measurements, stat))
+                });
+            match spawned {
+                Ok(handle) => handles.push((id, handle)),
+                Err(e) => {
+                    // 作成済みのスレッドはスコープを抜けるときに終了を待つ
+                    errors.push(format!(
+                        "thread creation failed after creating {} of {} threads: {}",
+                        handles.len(),
+                        nproc,
+                        e
+                    ));
+                    break;
+                }
+            }
+        }
+        let creation_nsec = diff_nsec(start, get_time());
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|(id, handle)| (id, handle.join()))
+            .collect();
+        ((results, errors), creation_nsec)
+    });
+    let (results, mut errors) = results;
+
+    let mut records = Vec::<(usize, Record)>::with_capacity(nproc * nrecord);
+    let mut schedstats = Vec::with_capacity(nproc);
+    for (id, result) in results {
+        match result {
+            Ok(Ok((measurements, stat))) => {
+                push_records(&mut records, id, &measurements, nrecord);
+                match stat {
+                    Some(Ok(stat)) => schedstats.push(stat),
+                    Some(Err(e)) => eprintln!("thread {}: {}", id, e),
+                    None => {}
+                }
+            }
+            Ok(Err(e)) => errors.push(format!("thread {}: {}", id, e)),
+            Err(_) => errors.push(format!("thread {} panicked", id)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!(errors.join("\n")));
+    }
+
+    Ok(Timeline::new(records, schedstats, creation_nsec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEASUREMENT: Measurement = Measurement {
+        begin_nsec: 120_000,
+        elapsed_nsec: 10_500_000,
+        cputime_nsec: 9_800_000,
+        cpu: 3,
+        nice: -5,
+    };
+
+    #[test]
+    fn measurement_round_trips_through_a_line() {
+        let line = MEASUREMENT.to_line();
+        assert_eq!(line, "120000\t10500000\t9800000\t3\t-5");
+        assert_eq!(Measurement::parse(&line), Some(MEASUREMENT));
+    }
+
+    #[test]
+    fn measurement_rejects_malformed_lines() {
+        for line in [
+            "",
+            "120000\t10500000\t9800000\t3",
+            "120000\t10500000\t9800000\t3\t-5\t0",
+            "120000 10500000 9800000 3 -5",
+            "120000\t10500000\t9800000\tx\t-5",
+            "-1\t10500000\t9800000\t3\t-5",
+        ] {
+            assert_eq!(
+                Measurement::parse(line),
+                None,
+                "{:?} should be rejected",
+                line
+            );
+        }
+    }
+
+    /// 子プロセスの代わりに `lines` を書き込んで閉じたパイプの読み出し側を返します。
+    fn piped(lines: &[String]) -> File {
+        let (read_fd, write_fd) = pipe().unwrap();
+        let mut out = unsafe { File::from_raw_fd(write_fd) };
+        for line in lines {
+            writeln!(out, "{}", line).unwrap();
+        }
+        unsafe { File::from_raw_fd(read_fd) }
+    }
+
+    #[test]
+    fn read_measurements_reads_until_the_pipe_closes() {
+        let input = piped(&[MEASUREMENT.to_line(), MEASUREMENT.to_line()]);
+        assert_eq!(read_measurements(&input).unwrap(), [MEASUREMENT; 2]);
+    }
+
+    #[test]
+    fn read_measurements_fails_on_a_malformed_record() {
+        let input = piped(&[MEASUREMENT.to_line(), "garbage".to_string()]);
+        let e = read_measurements(&input).unwrap_err();
+        assert_eq!(e.to_string(), "malformed record: \"garbage\"");
+    }
+}
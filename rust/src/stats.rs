@@ -0,0 +1,290 @@
+//! 計測値の要約統計量
+
+use serde::Serialize;
+
+/// 昇順に並べた `sorted` の `p` パーセンタイルを返します（最近傍順位法）。
+///
+/// `sorted` は空であってはいけません。
+pub fn percentile(sorted: &[u64], p: f64) -> u64 {
+    assert!(!sorted.is_empty(), "percentile of empty samples");
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// 両側 95% 信頼区間に使う t 分布の臨界値（自由度, 値）
+const T_95: [(usize, f64); 34] = [
+    (1, 12.706),
+    (2, 4.303),
+    (3, 3.182),
+    (4, 2.776),
+    (5, 2.571),
+    (6, 2.447),
+    (7, 2.365),
+    (8, 2.306),
+    (9, 2.262),
+    (10, 2.228),
+    (11, 2.201),
+    (12, 2.179),
+    (13, 2.160),
+    (14, 2.145),
+    (15, 2.131),
+    (16, 2.120),
+    (17, 2.110),
+    (18, 2.101),
+    (19, 2.093),
+    (20, 2.086),
+    (21, 2.080),
+    (22, 2.074),
+    (23, 2.069),
+    (24, 2.064),
+    (25, 2.060),
+    (26, 2.056),
+    (27, 2.052),
+    (28, 2.048),
+    (29, 2.045),
+    (30, 2.042),
+    (40, 2.021),
+    (60, 2.000),
+    (120, 1.980),
+    (usize::MAX, 1.960),
+];
+
+/// 自由度 `df` の t 分布の両側 95% 臨界値を返します（表にない自由度は、それより小さい最も近い自由度の値）。
+fn t_95(df: usize) -> f64 {
+    T_95.iter()
+        .rev()
+        .find(|&&(d, _)| d <= df)
+        .map_or(T_95[0].1, |&(_, t)| t)
+}
+
+/// 繰り返し測定した値の要約統計量
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    /// 測定回数
+    pub n: usize,
+    /// 平均値
+    pub mean: f64,
+    /// 標本標準偏差（測定回数が 1 回の場合は 0）
+    pub stddev: f64,
+    /// 中央値
+    pub median: f64,
+    /// 最小値
+    pub min: f64,
+    /// 最大値
+    pub max: f64,
+    /// 平均値の 95% 信頼区間の下限
+    pub ci95_low: f64,
+    /// 平均値の 95% 信頼区間の上限
+    pub ci95_high: f64,
+}
+
+impl Summary {
+    /// 測定値から要約統計量を求めます。
+    ///
+    /// `values` は空であってはいけません。
+    pub fn new(values: &[f64]) -> Summary {
+        assert!(!values.is_empty(), "summary of empty samples");
+        let n = values.len();
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let stddev = if n > 1 {
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        };
+        let margin = if n > 1 {
+            t_95(n - 1) * stddev / (n as f64).sqrt()
+        } else {
+            0.0
+        };
+        Summary {
+            n,
+            mean,
+            stddev,
+            median,
+            min: sorted[0],
+            max: sorted[n - 1],
+            ci95_low: mean - margin,
+            ci95_high: mean + margin,
+        }
+    }
+}
+
+/// 繰り返した測定から求めた、1 つの対象の 1 つの項目の要約統計量
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MetricSummary {
+    /// 対象（子プロセスやグループの番号、全体を表す `all` など）
+    pub target: String,
+    /// 項目の名前
+    pub metric: &'static str,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+impl MetricSummary {
+    /// 測定ごとの結果 `trials` から `value` で項目の値を取り出し、要約統計量を求めます。
+    pub fn new<T>(
+        target: impl ToString,
+        metric: &'static str,
+        trials: &[T],
+        value: impl Fn(&T) -> f64,
+    ) -> MetricSummary {
+        let values: Vec<f64> = trials.iter().map(value).collect();
+        MetricSummary {
+            target: target.to_string(),
+            metric,
+            summary: Summary::new(&values),
+        }
+    }
+}
+
+/// 区間を 2 のべき乗ごとに分け、さらにそれぞれを等分する数のビット数（32 等分で、32 以上の区間の幅は下限の 1/32 以下）
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// HDR Histogram と同じく、値の大きさに比例した幅の区間で数えるヒストグラム
+///
+/// `2^SUB_BUCKET_BITS` 未満の値は 1 ずつの区間で数え、それ以上の値は 2 のべき乗ごとの範囲を
+/// `2^SUB_BUCKET_BITS` 等分した区間で数えます。32 以上の値の区間は、幅が下限の 1/32 以下です。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// 値をまとめて数えたヒストグラムを作ります。
+    pub fn from_values(values: &[u64]) -> Histogram {
+        let mut histogram = Histogram::default();
+        for &value in values {
+            histogram.record(value);
+        }
+        histogram
+    }
+
+    /// 値を 1 つ数えます。
+    pub fn record(&mut self, value: u64) {
+        let index = Self::index(value);
+        if self.counts.len() <= index {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+    }
+
+    /// 値が入る区間の番号
+    fn index(value: u64) -> usize {
+        if value < SUB_BUCKETS {
+            return value as usize;
+        }
+        let shift = value.ilog2() - SUB_BUCKET_BITS;
+        ((shift as u64 + 1) * SUB_BUCKETS + (value >> shift) - SUB_BUCKETS) as usize
+    }
+
+    /// 区間の下限（この値を含む）と上限（この値を含まない。最後の区間は `u64::MAX`）
+    fn bounds(index: usize) -> (u64, u64) {
+        let index = index as u64;
+        if index < SUB_BUCKETS {
+            return (index, index + 1);
+        }
+        let shift = index / SUB_BUCKETS - 1;
+        let lower = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+        (lower, lower.saturating_add(1 << shift))
+    }
+
+    /// 値が 1 つ以上入った区間の `(下限, 上限, 数)` を小さい順に返します。
+    pub fn buckets(&self) -> Vec<(u64, u64, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(index, &count)| {
+                let (lower, upper) = Self::bounds(index);
+                (lower, upper, count)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 確かめる値（0, 31, 32, 63, 64 と、2 のべき乗およびその前後）
+    fn values() -> Vec<u64> {
+        let mut values = vec![0, 31, 32, 63, 64, u64::MAX];
+        for bit in 0..64 {
+            let power = 1u64 << bit;
+            values.extend([power - 1, power, power + 1]);
+        }
+        values
+    }
+
+    #[test]
+    fn histogram_bounds_contain_value() {
+        for value in values() {
+            let (lower, upper) = Histogram::bounds(Histogram::index(value));
+            assert!(lower <= value, "{}: [{}, {})", value, lower, upper);
+            assert!(
+                value < upper || upper == u64::MAX,
+                "{}: [{}, {})",
+                value,
+                lower,
+                upper
+            );
+        }
+    }
+
+    #[test]
+    fn histogram_index_of_lower_bound_round_trips() {
+        let last = Histogram::index(u64::MAX);
+        for index in 0..=last {
+            let (lower, upper) = Histogram::bounds(index);
+            assert_eq!(Histogram::index(lower), index);
+            assert_eq!(Histogram::index(upper - 1), index);
+            if index < last {
+                // 区間は隙間なく並ぶ
+                assert_eq!(Histogram::bounds(index + 1).0, upper);
+            }
+        }
+    }
+
+    #[test]
+    fn histogram_small_values_have_exact_buckets() {
+        for value in [0, 1, 31, 32] {
+            assert_eq!(
+                Histogram::bounds(Histogram::index(value)),
+                (value, value + 1)
+            );
+        }
+        assert_eq!(Histogram::bounds(Histogram::index(63)), (63, 64));
+        assert_eq!(Histogram::bounds(Histogram::index(64)), (64, 66));
+        assert_eq!(Histogram::bounds(Histogram::index(65)), (64, 66));
+    }
+
+    #[test]
+    fn histogram_bucket_width_is_at_most_1_32_of_lower_bound() {
+        for index in SUB_BUCKETS as usize..=Histogram::index(u64::MAX) {
+            let (lower, upper) = Histogram::bounds(index);
+            assert!(
+                (upper - lower) * SUB_BUCKETS <= lower,
+                "[{}, {})",
+                lower,
+                upper
+            );
+        }
+    }
+
+    #[test]
+    fn histogram_buckets_skip_empty_ones() {
+        let histogram = Histogram::from_values(&[1, 1, 40, 1000]);
+        assert_eq!(
+            histogram.buckets(),
+            [(1, 2, 2), (40, 41, 1), (992, 1008, 1)]
+        );
+    }
+}
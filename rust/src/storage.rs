@@ -0,0 +1,521 @@
+//! ストレージデバイスへの I/O の性能の計測
+//!
+//! 対象の先頭から [`PART_SIZE`] までの領域内に、指定したサイズの I/O を合計 [`ACCESS_SIZE`] だけ発行し、
+//! 最後に `fsync()` するまでの時間と、I/O 1 回ごとにかかった時間を計ります。
+//! I/O 1 回ごとの時間は、平均だけでなくパーセンタイルとヒストグラムで分布の裾まで表します。
+//! 複数のスレッドから同時に I/O を発行して（I/O の並列度）、デバイスの並列性を生かせるかも計れます。
+//!
+//! - シーケンシャルアクセスでは、領域の先頭から順番に読み書きします。
+//! - ランダムアクセスでは、領域をブロックサイズごとに区切った位置をシャッフルし、その先頭から順番に読み書きします。
+
+use crate::{
+    aligned::{AlignedBuf, Alignment},
+    args::positive,
+    clock::{diff_nsec, get_time, NSECS_PER_SEC},
+    fingerprint::Fingerprint,
+    stats::{percentile, Histogram},
+    testfile::LoopDevice,
+};
+use anyhow::{anyhow, Context};
+use clap::ArgEnum;
+use serde::Serialize;
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io,
+    os::unix::{
+        fs::{FileExt, FileTypeExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+    path::Path,
+    str::FromStr,
+    sync::RwLock,
+    thread,
+};
+
+nix::ioctl_read!(blkgetsize64, 0x12, 114, u64);
+
+/// I/O を発行する領域のサイズ（1GB）
+pub const PART_SIZE: usize = 1024 * 1024 * 1024;
+/// 発行する I/O の合計サイズ（64MB）
+pub const ACCESS_SIZE: usize = 64 * 1024 * 1024;
+
+/// カーネルによる I/O 支援機能（ページキャッシュ）を使うかどうか
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Help {
+    /// ページキャッシュを使う
+    On,
+    /// `O_DIRECT` でページキャッシュを使わずに読み書きする
+    Off,
+}
+
+/// 読み書きの種類
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// 読み出し
+    #[clap(name = "r")]
+    Read,
+    /// 書き込み
+    #[clap(name = "w")]
+    Write,
+}
+
+/// アクセスパターン
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// シーケンシャルアクセス
+    Seq,
+    /// ランダムアクセス
+    Rand,
+}
+
+impl Help {
+    /// メタデータに記録する名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Help::On => "on",
+            Help::Off => "off",
+        }
+    }
+}
+
+impl Op {
+    /// メタデータに記録する名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Read => "read",
+            Op::Write => "write",
+        }
+    }
+
+    /// I/O を発行するシステムコールの名前
+    fn syscall(&self) -> &'static str {
+        match self {
+            Op::Read => "pread",
+            Op::Write => "pwrite",
+        }
+    }
+}
+
+impl Pattern {
+    /// メタデータに記録する名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pattern::Seq => "seq",
+            Pattern::Rand => "rand",
+        }
+    }
+}
+
+/// `--iodepth 1,2,4` のように指定する、順に試す I/O の並列度の一覧
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepthList(pub Vec<usize>);
+
+impl FromStr for DepthList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|depth| {
+                positive(depth.trim()).map_err(|e| format!("invalid iodepth {:?}: {}", depth, e))
+            })
+            .collect::<Result<_, _>>()
+            .map(DepthList)
+    }
+}
+
+impl fmt::Display for DepthList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let depths: Vec<String> = self.0.iter().map(usize::to_string).collect();
+        f.write_str(&depths.join(","))
+    }
+}
+
+/// 実験条件
+#[derive(Serialize, Debug, Clone)]
+pub struct Metadata {
+    /// 実験プログラムの名前
+    pub program: &'static str,
+    /// I/O を発行したファイル
+    pub filename: String,
+    /// `--create` で作ったテストファイル（`filename` がループデバイスならそのつなぎ先）
+    pub testfile: Option<String>,
+    /// `--loop-device` でつないだループデバイスから、テストファイルへの I/O がダイレクト I/O か
+    pub loop_direct_io: Option<bool>,
+    /// カーネルによる I/O 支援機能（`on` または `off`）
+    pub help: &'static str,
+    /// 読み書きの種類
+    pub op: &'static str,
+    /// アクセスパターン
+    pub pattern: &'static str,
+    /// 1 回あたりの I/O サイズ（KB）
+    pub block_size_kb: usize,
+    /// I/O を発行する領域のサイズ（MB）
+    pub part_size_mb: usize,
+    /// 発行する I/O の合計サイズ（MB）
+    pub access_size_mb: usize,
+    /// ランダムアクセスの位置を決める乱数の種
+    pub seed: u64,
+    /// 試した I/O の並列度（`1,2,4` 形式）
+    pub iodepth: String,
+    /// バッファのアドレスの単位（バイト）
+    pub mem_align: Option<usize>,
+    /// ファイル上の位置と I/O サイズの単位（バイト）
+    pub offset_align: Option<usize>,
+    /// アラインメントの要件を得た方法
+    pub align_source: Option<&'static str>,
+    /// 実験した環境
+    #[serde(flatten)]
+    pub environment: Fingerprint,
+}
+
+impl Metadata {
+    /// 実験した環境（[`Fingerprint`]）の情報を加えてメタデータを作ります。
+    pub fn new(
+        filename: &Path,
+        help: Help,
+        op: Op,
+        pattern: Pattern,
+        block_size: usize,
+        seed: u64,
+        iodepths: &DepthList,
+    ) -> Metadata {
+        Metadata {
+            program: "io",
+            filename: filename.display().to_string(),
+            testfile: None,
+            loop_direct_io: None,
+            help: help.name(),
+            op: op.name(),
+            pattern: pattern.name(),
+            block_size_kb: block_size / 1024,
+            part_size_mb: PART_SIZE / 1024 / 1024,
+            access_size_mb: ACCESS_SIZE / 1024 / 1024,
+            seed,
+            iodepth: iodepths.to_string(),
+            mem_align: None,
+            offset_align: None,
+            align_source: None,
+            environment: Fingerprint::collect(),
+        }
+    }
+
+    /// 実験のために作ったテストファイルを記録します。
+    pub fn with_testfile(mut self, path: &Path) -> Metadata {
+        self.testfile = Some(path.display().to_string());
+        self
+    }
+
+    /// テストファイルをつないだループデバイスを記録します。
+    pub fn with_loop_device(mut self, device: &LoopDevice) -> Metadata {
+        self.loop_direct_io = Some(device.direct_io());
+        self
+    }
+
+    /// 対象のアラインメントの要件を記録します。
+    pub fn with_alignment(mut self, alignment: &Alignment) -> Metadata {
+        self.mem_align = Some(alignment.memory);
+        self.offset_align = Some(alignment.offset);
+        self.align_source = Some(alignment.source);
+        self
+    }
+}
+
+/// 1 回の計測の結果
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Trial {
+    /// 同時に I/O を発行したスレッドの数
+    pub iodepth: usize,
+    /// 計測の番号（ウォームアップを除く）
+    pub trial: usize,
+    /// 発行した I/O の回数
+    pub ios: usize,
+    /// 読み書きしたバイト数
+    pub bytes: usize,
+    /// 最初の I/O から `fsync()` の完了までの時間（秒）
+    pub elapsed_sec: f64,
+    /// スループット（MB/s）
+    pub mb_per_sec: f64,
+    /// 1 秒あたりの I/O の回数
+    pub iops: f64,
+    /// I/O 1 回あたりの時間の平均（μs、`fsync()` を含まない）
+    pub latency_mean_us: f64,
+    /// I/O 1 回あたりの時間の中央値（μs）
+    pub latency_p50_us: f64,
+    /// I/O 1 回あたりの時間の 90 パーセンタイル（μs）
+    pub latency_p90_us: f64,
+    /// I/O 1 回あたりの時間の 99 パーセンタイル（μs）
+    pub latency_p99_us: f64,
+    /// I/O 1 回あたりの時間の 99.9 パーセンタイル（μs）
+    pub latency_p999_us: f64,
+    /// I/O 1 回あたりの時間の最大値（μs）
+    pub latency_max_us: f64,
+}
+
+impl Trial {
+    /// 全体の時間と I/O ごとの時間（ns）から結果をまとめます。
+    pub fn new(
+        iodepth: usize,
+        trial: usize,
+        block_size: usize,
+        elapsed_nsec: u64,
+        latencies_nsec: &[u64],
+    ) -> Trial {
+        let ios = latencies_nsec.len();
+        let bytes = ios * block_size;
+        let elapsed_sec = elapsed_nsec as f64 / NSECS_PER_SEC as f64;
+        let mut sorted = latencies_nsec.to_vec();
+        sorted.sort_unstable();
+        let usec = |nsec: u64| nsec as f64 / 1000.0;
+        let percentile_us = |p| usec(percentile(&sorted, p));
+        Trial {
+            iodepth,
+            trial,
+            ios,
+            bytes,
+            elapsed_sec,
+            mb_per_sec: bytes as f64 / 1024.0 / 1024.0 / elapsed_sec,
+            iops: ios as f64 / elapsed_sec,
+            latency_mean_us: usec(sorted.iter().sum::<u64>()) / ios as f64,
+            latency_p50_us: percentile_us(50.0),
+            latency_p90_us: percentile_us(90.0),
+            latency_p99_us: percentile_us(99.0),
+            latency_p999_us: percentile_us(99.9),
+            latency_max_us: usec(sorted[ios - 1]),
+        }
+    }
+}
+
+/// I/O 1 回あたりの時間のヒストグラムの 1 区間
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyBucket {
+    /// 同時に I/O を発行したスレッドの数
+    pub iodepth: usize,
+    /// 計測の番号
+    pub trial: usize,
+    /// 区間の下限（ns、この値を含む）
+    pub lower_ns: u64,
+    /// 区間の上限（ns、この値を含まない）
+    pub upper_ns: u64,
+    /// 区間に入った I/O の回数
+    pub count: u64,
+}
+
+/// I/O ごとの時間（ns）から、値の大きさに比例した幅の区間で数えたヒストグラムを作ります（[`Histogram`]）。
+pub fn latency_histogram(
+    iodepth: usize,
+    trial: usize,
+    latencies_nsec: &[u64],
+) -> Vec<LatencyBucket> {
+    Histogram::from_values(latencies_nsec)
+        .buckets()
+        .into_iter()
+        .map(|(lower_ns, upper_ns, count)| LatencyBucket {
+            iodepth,
+            trial,
+            lower_ns,
+            upper_ns,
+            count,
+        })
+        .collect()
+}
+
+/// 対象を開きます。`help` が `Off` なら `O_DIRECT` を付けます。
+///
+/// デバイスファイルは `O_EXCL` で開くので、マウント中などで使用中なら失敗します。
+pub fn open(path: &Path, help: Help) -> io::Result<File> {
+    let mut flags = nix::libc::O_EXCL;
+    if help == Help::Off {
+        flags |= nix::libc::O_DIRECT;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(flags)
+        .open(path)
+}
+
+/// 開いた対象のサイズ（バイト）を返します。ブロックデバイスなら `BLKGETSIZE64` で得たデバイスのサイズです。
+pub fn size(file: &File) -> Result<u64, String> {
+    let metadata = file
+        .metadata()
+        .map_err(|e| format!("fstat() failed: {}", e))?;
+    if !metadata.file_type().is_block_device() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    unsafe { blkgetsize64(file.as_raw_fd(), &mut size) }
+        .map_err(|e| format!("ioctl(BLKGETSIZE64) failed: {}", e))?;
+    Ok(size)
+}
+
+/// I/O を発行する位置（バイト）を返します。
+///
+/// `block_size` は [`ACCESS_SIZE`] の約数でなければいけません。
+pub fn offsets(pattern: Pattern, block_size: usize, seed: u64) -> Vec<u64> {
+    let max_count = PART_SIZE / block_size;
+    let count = ACCESS_SIZE / block_size;
+    let mut indexes: Vec<usize> = (0..max_count).collect();
+    if pattern == Pattern::Rand {
+        // Fisher-Yates シャッフル
+        let mut rng = XorShift::new(seed);
+        for i in (1..max_count).rev() {
+            let j = (rng.next() % (i as u64 + 1)) as usize;
+            indexes.swap(i, j);
+        }
+    }
+    indexes
+        .into_iter()
+        .take(count)
+        .map(|i| (i * block_size) as u64)
+        .collect()
+}
+
+/// `offsets` の位置に `block_size` ずつ読み書きし、最後に `fsync()` します。
+///
+/// `offsets` を前から順に `iodepth` 個の重ならない範囲（数の差は高々 1）に分け、範囲ごとに 1 つのスレッドが
+/// 同期 I/O を発行するので、同時に最大 `iodepth` 個の I/O がデバイスに届きます。
+/// バッファはスレッドごとに、アドレスを `mem_align` に揃えて確保します。
+/// 全スレッドが揃って I/O を始めてから `fsync()` が終わるまでの時間（ns）と、I/O ごとの時間（ns）を返します。
+pub fn run(
+    file: &File,
+    op: Op,
+    offsets: &[u64],
+    block_size: usize,
+    mem_align: usize,
+    iodepth: usize,
+) -> anyhow::Result<(u64, Vec<u64>)> {
+    let slices = split(offsets, iodepth);
+    // 書き込みロックを外すまで、各スレッドは読み込みロックを待って I/O を始めない
+    let gate = RwLock::new(());
+    let (begin, results) = thread::scope(|scope| {
+        let start = gate.write().unwrap();
+        let mut handles = Vec::with_capacity(slices.len());
+        let mut spawn_error = None;
+        for (id, slice) in slices.iter().enumerate() {
+            let gate = &gate;
+            let spawned = thread::Builder::new()
+                .name(format!("io {}", id))
+                .spawn_scoped(scope, move || {
+                    drop(gate.read().unwrap());
+                    issue(file, op, slice, block_size, mem_align)
+                });
+            match spawned {
+                Ok(handle) => handles.push(handle),
+                Err(e) => {
+                    // 作成済みのスレッドはスコープを抜けるときに終了を待つ
+                    spawn_error = Some(anyhow!(
+                        "thread creation failed after creating {} of {} threads: {}",
+                        handles.len(),
+                        slices.len(),
+                        e
+                    ));
+                    break;
+                }
+            }
+        }
+        let begin = get_time();
+        drop(start);
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("I/O thread panicked"))
+            .collect();
+        match spawn_error {
+            Some(e) => Err(e),
+            None => Ok((begin, results)),
+        }
+    })?;
+
+    let mut latencies = Vec::with_capacity(offsets.len());
+    for result in results {
+        latencies.extend(result?);
+    }
+    file.sync_all().context("fsync() failed")?;
+    Ok((diff_nsec(begin, get_time()) as u64, latencies))
+}
+
+/// `offsets` を前から順に `n` 個の範囲に分けます。先頭から `offsets.len() % n` 個の範囲は、残りより 1 つ多くなります。
+fn split(offsets: &[u64], n: usize) -> Vec<&[u64]> {
+    let (len, extra) = (offsets.len() / n, offsets.len() % n);
+    let mut rest = offsets;
+    (0..n)
+        .map(|i| {
+            let (slice, tail) = rest.split_at(len + usize::from(i < extra));
+            rest = tail;
+            slice
+        })
+        .collect()
+}
+
+/// 1 つのスレッドが `offsets` の位置に順に同期 I/O を発行し、I/O ごとの時間（ns）を返します。
+fn issue(
+    file: &File,
+    op: Op,
+    offsets: &[u64],
+    block_size: usize,
+    mem_align: usize,
+) -> anyhow::Result<Vec<u64>> {
+    let mut buf = AlignedBuf::new(block_size, mem_align);
+    let mut latencies = Vec::with_capacity(offsets.len());
+    for &offset in offsets {
+        let before = get_time();
+        match op {
+            Op::Read => file.read_exact_at(&mut buf, offset),
+            Op::Write => file.write_all_at(&buf, offset),
+        }
+        .with_context(|| format!("{}() failed at offset {}", op.syscall(), offset))?;
+        latencies.push(diff_nsec(before, get_time()) as u64);
+    }
+    Ok(latencies)
+}
+
+/// ランダムアクセスの位置を決める疑似乱数（xorshift64）
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // 状態が 0 だと 0 しか返さない
+        XorShift(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_makes_exactly_n_near_equal_ranges() {
+        let offsets: Vec<u64> = (0..10).collect();
+        for n in 1..=10 {
+            let slices = split(&offsets, n);
+            assert_eq!(slices.len(), n);
+            let lens: Vec<usize> = slices.iter().map(|s| s.len()).collect();
+            assert!(lens.iter().max().unwrap() - lens.iter().min().unwrap() <= 1);
+            assert_eq!(slices.concat(), offsets);
+        }
+        // 2 つずつ区切ると 5 つにしかならない場合も、6 つに分ける
+        let lens: Vec<usize> = split(&offsets, 6).iter().map(|s| s.len()).collect();
+        assert_eq!(lens, [2, 2, 2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn depth_list_round_trips_and_rejects_zero() {
+        let depths: DepthList = "1, 2,4".parse().unwrap();
+        assert_eq!(depths, DepthList(vec![1, 2, 4]));
+        assert_eq!(depths.to_string(), "1,2,4");
+        for s in ["", "0", "1,", "x"] {
+            assert!(
+                s.parse::<DepthList>().is_err(),
+                "{:?} should be rejected",
+                s
+            );
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! 専用のパーティションがない環境で I/O の実験をするためのテストファイルとループデバイス
+//!
+//! どちらも値を破棄するときに後片付け（ループデバイスの切り離しとファイルの削除）をします。
+//! `SIGINT` などで中断した場合も、ループデバイスはプロセスの終了とともにカーネルが切り離しますが、
+//! ファイルは残るので `rm` で手動で消してください。
+
+use nix::{
+    errno::Errno,
+    fcntl::{fallocate, posix_fadvise, FallocateFlags, PosixFadviseAdvice},
+    libc::c_int,
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// 中身を書き込むときの 1 回あたりのサイズ
+const FILL_CHUNK: usize = 1024 * 1024;
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+/// 空いていたループデバイスを、つなぐ前に他のプロセスに使われた場合に試し直す回数
+const ATTACH_RETRIES: usize = 10;
+
+nix::ioctl_none_bad!(loop_ctl_get_free, 0x4C82);
+nix::ioctl_write_int_bad!(loop_set_fd, 0x4C00);
+nix::ioctl_none_bad!(loop_clr_fd, 0x4C01);
+nix::ioctl_write_int_bad!(loop_set_direct_io, 0x4C08);
+nix::ioctl_write_ptr_bad!(loop_set_status64, 0x4C04, LoopInfo64);
+nix::ioctl_read_bad!(loop_get_status64, 0x4C05, LoopInfo64);
+
+/// 最後に閉じられたときに自動で切り離すフラグ
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+
+/// `LOOP_GET_STATUS64` と `LOOP_SET_STATUS64` で使う構造体（`linux/loop.h` の `struct loop_info64`。libc にまだ定義がない）
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; 64],
+    lo_crypt_name: [u8; 64],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+/// 実験のために作ったファイル。破棄するときに削除する
+#[derive(Debug)]
+pub struct TestFile {
+    path: PathBuf,
+}
+
+impl TestFile {
+    /// `size` バイトの領域を `fallocate()` で確保し、中身を書き込んだファイルを新しく作ります。
+    ///
+    /// 既にファイルがあれば失敗します。
+    /// 確保しただけの領域は、読み出すとデバイスを読まずに 0 を返し、書き込むとファイルシステムが
+    /// 未書き込みの領域を書き込み済みに変える処理が加わるので、読み書きどちらの実験でも中身を書き込んでおきます。
+    /// 書き込んだデータはページキャッシュから追い出します。
+    pub fn create(path: &Path, size: usize) -> Result<TestFile, String> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| format!("{}: open() failed: {}", path.display(), e))?;
+        // ここからは失敗してもファイルを消す
+        let testfile = TestFile {
+            path: path.to_path_buf(),
+        };
+        let error = |call: &str, e: &dyn std::fmt::Display| {
+            format!("{}: {}() failed: {}", path.display(), call, e)
+        };
+
+        fallocate(file.as_raw_fd(), FallocateFlags::empty(), 0, size as i64)
+            .map_err(|e| error("fallocate", &e))?;
+        let chunk = vec![0xa5u8; FILL_CHUNK];
+        for _ in 0..size / FILL_CHUNK {
+            file.write_all(&chunk).map_err(|e| error("write", &e))?;
+        }
+        file.write_all(&chunk[..size % FILL_CHUNK])
+            .map_err(|e| error("write", &e))?;
+        file.sync_all().map_err(|e| error("fsync", &e))?;
+        posix_fadvise(
+            file.as_raw_fd(),
+            0,
+            size as i64,
+            PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+        )
+        .map_err(|e| error("posix_fadvise", &e))?;
+        Ok(testfile)
+    }
+
+    /// 作ったファイルのパス
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            eprintln!("{}: unlink() failed: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// ファイルをつないだループデバイス。破棄するときに切り離す
+#[derive(Debug)]
+pub struct LoopDevice {
+    path: PathBuf,
+    device: File,
+    direct_io: bool,
+}
+
+impl LoopDevice {
+    /// 空いているループデバイスに `backing` をつなぎます。
+    ///
+    /// ページキャッシュを二重に使わないよう、ループデバイスからファイルへの I/O はダイレクト I/O にします
+    /// （ファイルシステムが対応していなければ通常の I/O のまま。どちらになったかは [`LoopDevice::direct_io`] で分かる）。
+    ///
+    /// 空いていたループデバイスを、つなぐまでの間に他のプロセス（`losetup` など）に使われた場合は、
+    /// 空いているループデバイスを探し直します。
+    ///
+    /// 中断して [`Drop`] が呼ばれなくても残らないよう、つないだ直後に `LO_FLAGS_AUTOCLEAR` を設定し、
+    /// 最後に閉じられたとき（プロセスの終了時を含む）にカーネルが切り離すようにします。
+    pub fn attach(backing: &Path) -> Result<LoopDevice, String> {
+        let control = File::open(LOOP_CONTROL).map_err(|e| {
+            format!(
+                "{}: open() failed: {} (creating a loop device usually needs root)",
+                LOOP_CONTROL, e
+            )
+        })?;
+        let backing_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(backing)
+            .map_err(|e| format!("{}: open() failed: {}", backing.display(), e))?;
+
+        let mut retries = 0;
+        let (path, device) = loop {
+            let number = unsafe { loop_ctl_get_free(control.as_raw_fd()) }
+                .map_err(|e| format!("ioctl(LOOP_CTL_GET_FREE) failed: {}", e))?;
+            let path = PathBuf::from(format!("/dev/loop{}", number));
+            let device = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .map_err(|e| format!("{}: open() failed: {}", path.display(), e))?;
+            match unsafe { loop_set_fd(device.as_raw_fd(), backing_file.as_raw_fd() as c_int) } {
+                Ok(_) => break (path, device),
+                Err(Errno::EBUSY) if retries < ATTACH_RETRIES => retries += 1,
+                Err(e) => {
+                    return Err(format!(
+                        "{}: ioctl(LOOP_SET_FD) failed: {}",
+                        path.display(),
+                        e
+                    ))
+                }
+            }
+        };
+        if let Err(e) = set_autoclear(&device) {
+            if let Err(e) = unsafe { loop_clr_fd(device.as_raw_fd()) } {
+                eprintln!("{}: ioctl(LOOP_CLR_FD) failed: {}", path.display(), e);
+            }
+            return Err(format!("{}: {}", path.display(), e));
+        }
+        let direct_io = match unsafe { loop_set_direct_io(device.as_raw_fd(), 1) } {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!(
+                    "{}: ioctl(LOOP_SET_DIRECT_IO) failed: {}; the backing file is accessed through the page cache",
+                    path.display(),
+                    e
+                );
+                false
+            }
+        };
+
+        Ok(LoopDevice {
+            path,
+            device,
+            direct_io,
+        })
+    }
+
+    /// ループデバイスのパス（`/dev/loopN`）
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// ループデバイスからファイルへの I/O がダイレクト I/O か
+    pub fn direct_io(&self) -> bool {
+        self.direct_io
+    }
+}
+
+/// つないだループデバイスに `LO_FLAGS_AUTOCLEAR` を設定します。
+fn set_autoclear(device: &File) -> Result<(), String> {
+    let mut info: LoopInfo64 = unsafe { std::mem::zeroed() };
+    unsafe { loop_get_status64(device.as_raw_fd(), &mut info) }
+        .map_err(|e| format!("ioctl(LOOP_GET_STATUS64) failed: {}", e))?;
+    info.lo_flags |= LO_FLAGS_AUTOCLEAR;
+    unsafe { loop_set_status64(device.as_raw_fd(), &info) }
+        .map_err(|e| format!("ioctl(LOOP_SET_STATUS64) failed: {}", e))?;
+    Ok(())
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        // 他にデバイスを開いているものがあれば、最後に閉じられたときに切り離される
+        if let Err(e) = unsafe { loop_clr_fd(self.device.as_raw_fd()) } {
+            eprintln!("{}: ioctl(LOOP_CLR_FD) failed: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! 記録から求めた、連続して動作した時間（タイムスライス）と待たされた時間の分布
+//!
+//! 子プロセスは採取間隔ごとに経過時間と消費 CPU 時間を記録しています。
+//! 隣り合う記録の間で、経過時間の増分が CPU 時間の増分を `threshold` 以上上回っていれば、
+//! その間に他のプロセスへ CPU が切り替わって待たされたとみなします。
+//! 待たされた区間で区切った、連続して動作した CPU 時間の合計をタイムスライスとします。
+//!
+//! 切り替わりは採取間隔の単位でしか分からないため、1 つの採取間隔の中で複数回切り替わった場合は
+//! 1 回の待ちとして数え、待ちはその採取間隔の始めに起きたものとして扱います。
+//! タイムスライスより十分短い採取間隔で記録すると、分布が正確になります。
+
+use crate::{clock::NSECS_PER_MSEC, output::Record, stats::Histogram};
+use serde::Serialize;
+
+/// 1 つの子プロセスのタイムスライスと待ち時間（ns）
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Slices {
+    /// プロセス番号
+    pub id: usize,
+    /// 連続して動作した CPU 時間
+    pub runs: Vec<u64>,
+    /// 待たされた時間
+    pub waits: Vec<u64>,
+}
+
+/// 子プロセスごとに、記録をタイムスライスと待ち時間に分けます。
+///
+/// 最初の記録は、子プロセスが CPU 時間を計り始めた時点（経過時間 `begin_nsec`、CPU 時間 0）からの増分として扱います。
+pub fn split(records: &[Record], nproc: usize, threshold_nsec: u64) -> Vec<Slices> {
+    let mut slices: Vec<Slices> = (0..nproc)
+        .map(|id| Slices {
+            id,
+            ..Slices::default()
+        })
+        .collect();
+    // 子プロセスごとの、直前の記録の (経過時間, CPU 時間) と動作中のタイムスライスの長さ
+    let mut prev: Vec<Option<(u64, u64)>> = vec![None; nproc];
+    let mut running = vec![0u64; nproc];
+
+    for record in records {
+        let id = record.id;
+        let (elapsed, cputime) = (record.elapsed_nsec as u64, record.cputime_nsec as u64);
+        let (prev_elapsed, prev_cputime) = prev[id].unwrap_or((record.begin_nsec as u64, 0));
+        let ran = cputime.saturating_sub(prev_cputime);
+        let waited = elapsed.saturating_sub(prev_elapsed).saturating_sub(ran);
+        if waited >= threshold_nsec {
+            if running[id] > 0 {
+                slices[id].runs.push(running[id]);
+            }
+            slices[id].waits.push(waited);
+            running[id] = 0;
+        }
+        running[id] += ran;
+        prev[id] = Some((elapsed, cputime));
+    }
+    for (slices, running) in slices.iter_mut().zip(running) {
+        if running > 0 {
+            slices.runs.push(running);
+        }
+    }
+    slices
+}
+
+/// ヒストグラムの 1 区間
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bucket {
+    /// 値の種類（`timeslice` または `wait`）
+    pub kind: &'static str,
+    /// 区間の下限（μs、この値を含む）
+    pub lower_us: u64,
+    /// 区間の上限（μs、この値を含まない）
+    pub upper_us: u64,
+    /// 区間に入った数
+    pub count: u64,
+}
+
+/// μs 単位に切り捨てた値を [`Histogram`] で数え、値が 1 つ以上入った区間を小さい順に返します。
+pub fn histogram(kind: &'static str, values_nsec: &[u64]) -> Vec<Bucket> {
+    let values_us: Vec<u64> = values_nsec.iter().map(|value| value / 1000).collect();
+    Histogram::from_values(&values_us)
+        .buckets()
+        .into_iter()
+        .map(|(lower_us, upper_us, count)| Bucket {
+            kind,
+            lower_us,
+            upper_us,
+            count,
+        })
+        .collect()
+}
+
+/// 1 つの子プロセスのタイムスライスと待ち時間の要約
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SliceSummary {
+    /// プロセス番号
+    pub id: usize,
+    /// タイムスライスの数
+    pub timeslices: usize,
+    /// タイムスライスの平均（ms）
+    pub timeslice_mean_ms: f64,
+    /// タイムスライスの最大値（ms）
+    pub timeslice_max_ms: f64,
+    /// 待たされた回数
+    pub waits: usize,
+    /// 待ち時間の平均（ms）
+    pub wait_mean_ms: f64,
+    /// 待ち時間の最大値（ms）
+    pub wait_max_ms: f64,
+}
+
+impl From<&Slices> for SliceSummary {
+    fn from(slices: &Slices) -> Self {
+        let mean = |values: &[u64]| match values.len() {
+            0 => 0.0,
+            n => msec(values.iter().sum::<u64>() / n as u64),
+        };
+        let max = |values: &[u64]| msec(values.iter().copied().max().unwrap_or(0));
+        SliceSummary {
+            id: slices.id,
+            timeslices: slices.runs.len(),
+            timeslice_mean_ms: mean(&slices.runs),
+            timeslice_max_ms: max(&slices.runs),
+            waits: slices.waits.len(),
+            wait_mean_ms: mean(&slices.waits),
+            wait_max_ms: max(&slices.waits),
+        }
+    }
+}
+
+/// ns を小数点以下 3 桁までの ms にします。
+fn msec(nsec: u64) -> f64 {
+    (nsec as f64 / NSECS_PER_MSEC as f64 * 1000.0).round() / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: usize = NSECS_PER_MSEC;
+
+    /// 経過時間と CPU 時間を ms で指定した記録
+    fn record(id: usize, begin_ms: usize, elapsed_ms: usize, cputime_ms: usize) -> Record {
+        Record {
+            id,
+            time_ms: elapsed_ms,
+            progress: 0,
+            cputime_ms,
+            cpu: 0,
+            nice: 0,
+            elapsed_nsec: elapsed_ms * MS,
+            cputime_nsec: cputime_ms * MS,
+            begin_nsec: begin_ms * MS,
+        }
+    }
+
+    fn slices(id: usize, runs_ms: &[u64], waits_ms: &[u64]) -> Slices {
+        let ns = |values: &[u64]| values.iter().map(|v| v * MS as u64).collect();
+        Slices {
+            id,
+            runs: ns(runs_ms),
+            waits: ns(waits_ms),
+        }
+    }
+
+    #[test]
+    fn split_separates_runs_at_waits() {
+        // 子プロセス 0 は 2〜4ms の間、子プロセス 1 は 3〜5ms の間に 1ms ずつ待たされている
+        let records = [
+            record(0, 0, 1, 1),
+            record(1, 2, 3, 1),
+            record(0, 0, 2, 2),
+            record(0, 0, 4, 3),
+            record(1, 2, 5, 2),
+            record(0, 0, 5, 4),
+            record(1, 2, 6, 3),
+        ];
+        assert_eq!(
+            split(&records, 2, 100_000),
+            [slices(0, &[2, 2], &[1]), slices(1, &[1, 2], &[1])]
+        );
+    }
+
+    #[test]
+    fn split_counts_wait_before_first_record() {
+        // 計り始めてから最初の記録までに 1ms 待たされている
+        let records = [record(0, 0, 2, 1), record(0, 0, 3, 2)];
+        assert_eq!(split(&records, 1, 100_000), [slices(0, &[2], &[1])]);
+    }
+
+    #[test]
+    fn split_ignores_waits_below_threshold() {
+        let mut records = [record(0, 0, 1, 1), record(0, 0, 2, 2)];
+        records[1].elapsed_nsec += 50_000;
+        assert_eq!(split(&records, 1, 100_000), [slices(0, &[2], &[])]);
+        assert_eq!(split(&records, 1, 50_000).first().unwrap().waits, [50_000]);
+    }
+
+    #[test]
+    fn split_returns_empty_slices_for_children_without_records() {
+        assert_eq!(
+            split(&[], 2, 100_000),
+            [slices(0, &[], &[]), slices(1, &[], &[])]
+        );
+    }
+
+    fn bucket(lower_us: u64, upper_us: u64, count: u64) -> Bucket {
+        Bucket {
+            kind: "wait",
+            lower_us,
+            upper_us,
+            count,
+        }
+    }
+
+    #[test]
+    fn histogram_counts_microseconds() {
+        let values = [0, 500, 1_000, 1_999, 2_000, 5_000];
+        assert_eq!(
+            histogram("wait", &values),
+            [
+                bucket(0, 1, 2),
+                bucket(1, 2, 2),
+                bucket(2, 3, 1),
+                bucket(5, 6, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_skips_empty_buckets_and_widens_with_value() {
+        // 32μs 以上の区間は幅が下限の 1/32 以下になる（100μs は [100, 102)）
+        assert_eq!(
+            histogram("wait", &[4_000, 100_000, 101_999]),
+            [bucket(4, 5, 1), bucket(100, 102, 2)]
+        );
+        assert_eq!(histogram("wait", &[]), []);
+    }
+}
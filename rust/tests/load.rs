@@ -0,0 +1,47 @@
+//! [`playground::load`] の推定結果どおりに CPU 時間を消費できることを確認します。
+//!
+//! 最適化によって負荷処理が消えていないことを確かめるのが目的なので、
+//! デバッグビルドでは無視し、リリースプロファイルでだけ実行します。
+//!
+//! ```shellsession
+//! $ cargo test --release --test load
+//! ```
+
+use playground::{
+    clock::{diff_nsec, get_time, NSECS_PER_MSEC},
+    load::{calibrate, load, NSAMPLE_FOR_ESTIMATION},
+};
+
+const REQUESTED_MSEC: usize = 10;
+const NTRIAL: usize = 5;
+
+#[test]
+#[cfg_attr(
+    debug_assertions,
+    ignore = "run under the release profile: cargo test --release --test load"
+)]
+fn requested_load_takes_requested_time() {
+    let calibration = calibrate(NSAMPLE_FOR_ESTIMATION);
+    let nloop = calibration.loops_per_msec() * REQUESTED_MSEC;
+
+    // 他のプロセスに割り込まれた試行の影響を避けるため中央値で比較する
+    let mut elapsed: Vec<usize> = (0..NTRIAL)
+        .map(|_| {
+            let before = get_time();
+            load(nloop);
+            let after = get_time();
+            diff_nsec(before, after)
+        })
+        .collect();
+    elapsed.sort_unstable();
+    let median_msec = elapsed[NTRIAL / 2] as f64 / NSECS_PER_MSEC as f64;
+
+    let requested = REQUESTED_MSEC as f64;
+    assert!(
+        (requested * 0.5..requested * 1.5).contains(&median_msec),
+        "requested {}ms of load but took {:.2}ms (calibration: {:?})",
+        REQUESTED_MSEC,
+        median_msec,
+        calibration
+    );
+}